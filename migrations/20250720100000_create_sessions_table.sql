@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE sessions (
+    session_key TEXT NOT NULL,
+    user_id uuid NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    state JSONB NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (session_key)
+);
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
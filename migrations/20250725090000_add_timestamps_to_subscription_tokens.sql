@@ -0,0 +1,4 @@
+-- Tokens expire after a while and can only be used once.
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN consumed_at timestamptz NULL;
@@ -0,0 +1,41 @@
+-- A deployment can run several publications (lists). Requests that do not
+-- name a list use the default one.
+CREATE TABLE newsletters(
+    newsletter_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    is_default BOOLEAN NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_id)
+);
+CREATE UNIQUE INDEX newsletters_single_default ON newsletters (is_default) WHERE is_default;
+INSERT INTO newsletters (newsletter_id, name, is_default)
+VALUES (gen_random_uuid(), 'Newsletter', true);
+
+-- Membership of a subscriber in a list, each with its own status.
+CREATE TABLE newsletter_subscriptions(
+    newsletter_id uuid NOT NULL
+        REFERENCES newsletters (newsletter_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_id, subscriber_id)
+);
+INSERT INTO newsletter_subscriptions (newsletter_id, subscriber_id, status, subscribed_at)
+SELECT n.newsletter_id, s.id, s.status, s.subscribed_at
+FROM subscriptions s CROSS JOIN newsletters n
+WHERE n.is_default;
+-- `subscriptions.status` now only tracks whether the address was confirmed.
+UPDATE subscriptions SET status = 'confirmed' WHERE status = 'unsubscribed';
+
+ALTER TABLE subscription_tokens
+    ADD COLUMN newsletter_id uuid REFERENCES newsletters (newsletter_id);
+UPDATE subscription_tokens
+SET newsletter_id = (SELECT newsletter_id FROM newsletters WHERE is_default);
+ALTER TABLE subscription_tokens ALTER COLUMN newsletter_id SET NOT NULL;
+
+ALTER TABLE newsletter_issues
+    ADD COLUMN newsletter_id uuid REFERENCES newsletters (newsletter_id);
+UPDATE newsletter_issues
+SET newsletter_id = (SELECT newsletter_id FROM newsletters WHERE is_default);
+ALTER TABLE newsletter_issues ALTER COLUMN newsletter_id SET NOT NULL;
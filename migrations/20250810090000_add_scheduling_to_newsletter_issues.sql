@@ -0,0 +1,7 @@
+-- Issues can be scheduled for later: they are only enqueued for delivery
+-- once `send_at` has passed. `published_at` is set when they are enqueued.
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published',
+    ADD COLUMN send_at timestamptz NULL;
+CREATE INDEX newsletter_issues_due ON newsletter_issues (send_at)
+    WHERE status = 'scheduled';
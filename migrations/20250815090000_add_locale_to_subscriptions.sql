@@ -0,0 +1,2 @@
+-- Subscribers without a locale get emails in the default one.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NULL;
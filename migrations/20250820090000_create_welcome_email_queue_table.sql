@@ -0,0 +1,8 @@
+-- Welcome emails waiting to be sent once a subscription is confirmed.
+CREATE TABLE welcome_email_queue (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    newsletter_id uuid NOT NULL REFERENCES newsletters (newsletter_id),
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (subscriber_id, newsletter_id)
+);
@@ -0,0 +1,8 @@
+-- Keep in sync with `SubscriberStatus`. Transitions between them are
+-- checked by the application.
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_status_check CHECK (
+    status IN ('pending_confirmation', 'confirmed', 'unsubscribed', 'bounced', 'complained')
+);
+ALTER TABLE newsletter_subscriptions ADD CONSTRAINT newsletter_subscriptions_status_check CHECK (
+    status IN ('pending_confirmation', 'confirmed', 'unsubscribed', 'bounced', 'complained')
+);
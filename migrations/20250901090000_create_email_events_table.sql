@@ -0,0 +1,14 @@
+-- Feedback from the email provider about messages we sent, as received by
+-- the webhook. `payload` keeps the original event for troubleshooting.
+CREATE TABLE email_events(
+    id uuid NOT NULL,
+    event_type TEXT NOT NULL CHECK (event_type IN ('delivery', 'bounce', 'spam_complaint')),
+    bounce_type TEXT NULL,
+    email TEXT NOT NULL,
+    message_id TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now(),
+    payload JSONB NOT NULL,
+    PRIMARY KEY (id)
+);
+CREATE INDEX email_events_email ON email_events (email);
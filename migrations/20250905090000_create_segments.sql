@@ -0,0 +1,24 @@
+-- Free-form labels used to build segments, e.g. interests or plans.
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag ON subscriber_tags (tag);
+
+-- Saved audience filters, see `segments.rs` for the definition format.
+CREATE TABLE segments(
+    segment_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    definition JSONB NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (segment_id)
+);
+
+-- The definition is copied when the issue is created, so that deleting the
+-- segment does not change who a scheduled issue goes to.
+ALTER TABLE newsletter_issues
+    ADD COLUMN segment_id uuid NULL
+        REFERENCES segments (segment_id) ON DELETE SET NULL,
+    ADD COLUMN segment_definition JSONB NULL;
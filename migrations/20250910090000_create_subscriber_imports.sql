@@ -0,0 +1,20 @@
+-- Bulk imports of subscribers. Pre-confirmed imports skip the confirmation
+-- email, so they must say where the consent was collected.
+CREATE TABLE subscriber_imports(
+    import_id uuid NOT NULL,
+    newsletter_id uuid NOT NULL
+        REFERENCES newsletters (newsletter_id),
+    imported_by uuid NULL REFERENCES users (user_id),
+    pre_confirmed BOOLEAN NOT NULL,
+    consent_source TEXT NULL,
+    imported INTEGER NOT NULL DEFAULT 0,
+    duplicates INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (import_id),
+    CHECK (NOT pre_confirmed OR consent_source IS NOT NULL)
+);
+
+-- The import a membership came from, if any.
+ALTER TABLE newsletter_subscriptions
+    ADD COLUMN import_id uuid NULL REFERENCES subscriber_imports (import_id);
@@ -0,0 +1,3 @@
+-- When the membership was last confirmed. Unknown for memberships
+-- confirmed before this column existed.
+ALTER TABLE newsletter_subscriptions ADD COLUMN confirmed_at timestamptz NULL;
@@ -0,0 +1,21 @@
+-- Links emailed to subscribers who ask for their data or for its erasure.
+-- Only a hash of the token is kept, as the link gives access to the data.
+CREATE TABLE personal_data_tokens(
+    token_hash TEXT NOT NULL,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (token_hash)
+);
+
+-- One row per erased subscriber, with nothing that identifies them.
+CREATE TABLE subscriber_erasures(
+    erasure_id uuid NOT NULL,
+    requested_by TEXT NOT NULL CHECK (requested_by IN ('subscriber', 'admin')),
+    admin_id uuid NULL REFERENCES users (user_id),
+    memberships INTEGER NOT NULL,
+    anonymized_deliveries INTEGER NOT NULL,
+    deleted_email_events INTEGER NOT NULL,
+    erased_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (erasure_id)
+);
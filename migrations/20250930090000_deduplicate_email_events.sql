@@ -0,0 +1,15 @@
+-- Postmark sends a webhook again when it times out. Keep the first copy
+-- of every event, and only that one from now on.
+DELETE FROM email_events e
+USING email_events earlier
+WHERE e.message_id IS NOT NULL
+    AND e.event_type = earlier.event_type
+    AND e.message_id = earlier.message_id
+    AND lower(e.email) = lower(earlier.email)
+    AND (earlier.received_at, earlier.id) < (e.received_at, e.id);
+CREATE UNIQUE INDEX email_events_unique_message
+    ON email_events (event_type, message_id, lower(email))
+    WHERE message_id IS NOT NULL;
+
+-- Providers do not always report addresses in the case they were typed in.
+CREATE INDEX subscriptions_lower_email ON subscriptions (lower(email));
@@ -0,0 +1,4 @@
+-- The language asked for when signing up again with a known address. It
+-- only replaces the subscriber's once the link is used, so that nobody but
+-- the owner of the address can change it.
+ALTER TABLE subscription_tokens ADD COLUMN locale TEXT NULL;
@@ -0,0 +1,9 @@
+-- Personal data requests waiting to be emailed. Every valid address is
+-- queued, known or not, so that the response gives nothing away; the
+-- worker drops those that match no subscriber.
+CREATE TABLE personal_data_email_queue (
+    email TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (email)
+);
@@ -0,0 +1,12 @@
+-- Confirmation emails waiting to be sent. Every signup queues one, even
+-- for members who are confirmed already, so that answering takes as long
+-- whatever we know about the address; the worker drops those.
+CREATE TABLE confirmation_email_queue (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    newsletter_id uuid NOT NULL REFERENCES newsletters (newsletter_id),
+    -- Stored with the token, and applied once the link is used.
+    locale TEXT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (subscriber_id, newsletter_id)
+);
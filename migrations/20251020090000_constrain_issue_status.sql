@@ -0,0 +1,4 @@
+-- Keep in sync with `IssueStatus`.
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check CHECK (
+    status IN ('draft', 'scheduled', 'published', 'cancelled')
+);
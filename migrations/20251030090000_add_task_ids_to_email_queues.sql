@@ -0,0 +1,10 @@
+-- Lets one worker implementation serve every email queue: tasks are picked,
+-- retried and removed by `task_id`, whatever identifies them otherwise.
+ALTER TABLE confirmation_email_queue
+    ADD COLUMN task_id BIGINT GENERATED ALWAYS AS IDENTITY UNIQUE;
+ALTER TABLE welcome_email_queue
+    ADD COLUMN task_id BIGINT GENERATED ALWAYS AS IDENTITY UNIQUE;
+ALTER TABLE personal_data_email_queue
+    ADD COLUMN task_id BIGINT GENERATED ALWAYS AS IDENTITY UNIQUE;
+ALTER TABLE unsubscribe_email_queue
+    ADD COLUMN task_id BIGINT GENERATED ALWAYS AS IDENTITY UNIQUE;
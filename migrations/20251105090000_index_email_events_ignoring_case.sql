@@ -0,0 +1,3 @@
+-- Email events are looked up ignoring case, like the webhook matches them.
+DROP INDEX email_events_email;
+CREATE INDEX email_events_lower_email ON email_events (lower(email));
@@ -0,0 +1,7 @@
+-- Since lists were introduced, `subscriptions.status` only recorded whether
+-- the address was ever confirmed, next to the status of each membership in
+-- `newsletter_subscriptions`. Only the latter is a status now.
+ALTER TABLE subscriptions ADD COLUMN address_confirmed BOOLEAN NOT NULL DEFAULT false;
+UPDATE subscriptions SET address_confirmed = true WHERE status <> 'pending_confirmation';
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_status_check;
+ALTER TABLE subscriptions DROP COLUMN status;
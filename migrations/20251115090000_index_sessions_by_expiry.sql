@@ -0,0 +1,2 @@
+-- Expired sessions are deleted whenever a new one is saved.
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
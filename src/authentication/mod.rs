@@ -0,0 +1,12 @@
+mod middleware;
+mod password;
+mod password_policy;
+mod session_store;
+
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{
+    change_password, get_username, validate_credentials, verify_password_hash, AuthError,
+    Credentials,
+};
+pub use password_policy::PasswordPolicy;
+pub use session_store::PgSessionStore;
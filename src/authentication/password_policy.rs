@@ -0,0 +1,86 @@
+use secrecy::{ExposeSecret, Secret};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Requirements a new password has to meet.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// How many of lowercase letters, uppercase letters, digits and other
+    /// symbols must appear in the password.
+    pub min_character_classes: usize,
+}
+
+impl PasswordPolicy {
+    pub fn check(&self, password: &Secret<String>) -> Result<(), String> {
+        let password = password.expose_secret();
+        let length = password.graphemes(true).count();
+        if length < self.min_length {
+            return Err(format!(
+                "The new password must be at least {} characters long.",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            return Err(format!(
+                "The new password must be at most {} characters long.",
+                self.max_length
+            ));
+        }
+        let classes: [fn(char) -> bool; 4] = [
+            char::is_lowercase,
+            char::is_uppercase,
+            |c| c.is_numeric(),
+            |c| !c.is_alphanumeric(),
+        ];
+        let n_classes = classes
+            .iter()
+            .filter(|class| password.chars().any(**class))
+            .count();
+        if n_classes < self.min_character_classes {
+            return Err(format!(
+                "The new password must mix at least {} of lowercase letters, \
+                uppercase letters, digits and symbols.",
+                self.min_character_classes
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordPolicy;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: 128,
+            min_character_classes: 3,
+        }
+    }
+
+    #[test]
+    fn a_short_password_is_rejected() {
+        assert_err!(policy().check(&Secret::new("aB3$".to_string())));
+    }
+
+    #[test]
+    fn a_too_long_password_is_rejected() {
+        assert_err!(policy().check(&Secret::new("aB3$".repeat(33))));
+    }
+
+    #[test]
+    fn a_password_with_too_few_character_classes_is_rejected() {
+        assert_err!(policy().check(&Secret::new("onlylowercaseletters".to_string())));
+        assert_err!(policy().check(&Secret::new("lowercase0123456".to_string())));
+    }
+
+    #[test]
+    fn a_password_meeting_the_policy_is_accepted() {
+        assert_ok!(policy().check(&Secret::new("correct-horse-battery-1".to_string())));
+        assert_ok!(policy().check(&Secret::new("Correct Horse Battery".to_string())));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::session_state::TypedSession;
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+type SessionState = HashMap<String, String>;
+
+/// Keeps session state in the `sessions` table.
+///
+/// The id of the logged-in user, if any, is mirrored in its own column so
+/// that all the sessions of a user can be invalidated at once.
+/// Expired sessions are deleted whenever a new one is saved.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn user_id(state: &SessionState) -> Option<Uuid> {
+    state
+        .get(TypedSession::USER_ID_KEY)
+        .and_then(|v| serde_json::from_str(v).ok())
+}
+
+fn generate_session_key() -> SessionKey {
+    Alphanumeric
+        .sample_string(&mut rand::thread_rng(), 64)
+        .try_into()
+        .expect("A 64 characters long string is a valid session key")
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state
+            FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load session state.")
+        .map_err(LoadError::Other)?;
+        row.map(|r| serde_json::from_value(r.state))
+            .transpose()
+            .context("Failed to deserialize session state.")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize session state.")
+            .map_err(SaveError::Serialization)?;
+        sqlx::query!(
+            r#"
+            WITH expired AS (
+                DELETE FROM sessions WHERE expires_at <= now()
+            )
+            INSERT INTO sessions (session_key, user_id, state, expires_at)
+            VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+            "#,
+            session_key.as_ref(),
+            user_id(&session_state),
+            state,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save session state.")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize session state.")
+            .map_err(UpdateError::Serialization)?;
+        // A session deleted in the meantime (e.g. invalidated after a
+        // password change) is not brought back to life.
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET
+                user_id = $2,
+                state = $3,
+                expires_at = now() + make_interval(secs => $4)
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            user_id(&session_state),
+            state,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session state.")
+        .map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET expires_at = now() + make_interval(secs => $2)
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session TTL.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete session.")?;
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
+use crate::authentication::PasswordPolicy;
 use secrecy::{Secret, ExposeSecret};
 use sqlx::postgres::PgConnectOptions;
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailClient, FileSinkTransport, PostmarkTransport, RetryPolicy, SmtpTransport
+};
 use serde_aux::field_attributes::deserialize_number_from_string;
 
 #[derive(serde::Deserialize, Clone)]
@@ -8,17 +12,165 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub postmark_webhook: PostmarkWebhookSettings,
+    pub email_templates: EmailTemplateSettings,
+    pub welcome_email: WelcomeEmailSettings,
+    pub password: PasswordSettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WelcomeEmailSettings {
+    /// Send a welcome email once a subscription is confirmed.
+    pub enabled: bool,
+    /// Replaces the subject of the `welcome` template in every locale.
+    pub subject: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailTemplateSettings {
+    /// Files found here take precedence over the built-in templates.
+    pub override_directory: Option<String>,
+    /// Used for subscribers whose locale has no translation.
+    pub default_locale: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordSettings {
+    pub policy: PasswordPolicy,
+    pub hashing: PasswordHashingSettings,
+}
+
+/// Argon2id cost parameters used when hashing new passwords.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    pub memory_cost_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            self.memory_cost_kib,
+            self.iterations,
+            self.parallelism,
+            None
+        )
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
+    pub transport: EmailTransportKind,
     pub sender_email: String,
+    pub timeout_milliseconds: u64,
+    pub postmark: PostmarkSettings,
+    pub smtp: SmtpSettings,
+    pub file_sink: FileSinkSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransportKind {
+    Postmark,
+    Smtp,
+    FileSink,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PostmarkSettings {
+    pub base_url: String,
     pub authorization_token: Secret<String>,
-    pub timeout_milliseconds: u64
+    pub retry: RetrySettings,
+}
+
+/// Basic auth credentials Postmark must send with webhook requests, set as
+/// part of the webhook URL in the Postmark UI.
+#[derive(serde::Deserialize, Clone)]
+pub struct PostmarkWebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileSinkSettings {
+    pub path: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RetrySettings {
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+    pub retryable_status_codes: Vec<u16>,
+    pub honor_retry_after: bool,
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+            retryable_status_codes: self.retryable_status_codes.clone(),
+            honor_retry_after: self.honor_retry_after,
+        }
+    }
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let retry_policy = self.postmark.retry.policy();
+        self.client_with_retry_policy(retry_policy)
+    }
+
+    /// For the queue workers, which retry failed sends themselves, later,
+    /// rather than holding on to the task in the meantime.
+    pub fn client_without_retries(self) -> EmailClient {
+        self.client_with_retry_policy(RetryPolicy::none())
+    }
+
+    fn client_with_retry_policy(self, retry_policy: RetryPolicy) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        match self.transport {
+            EmailTransportKind::Postmark => {
+                let transport = PostmarkTransport::new(
+                    self.postmark.base_url,
+                    self.postmark.authorization_token,
+                    timeout,
+                    retry_policy
+                );
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::Smtp => {
+                let credentials = self.smtp.username.zip(self.smtp.password);
+                let transport = SmtpTransport::new(
+                    &self.smtp.host,
+                    self.smtp.port,
+                    credentials,
+                    timeout
+                );
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::FileSink => {
+                let transport = FileSinkTransport::new(self.file_sink.path.into());
+                EmailClient::new(sender_email, transport)
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -33,7 +185,18 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
-    pub base_url: String
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    /// How long a subscription confirmation link stays valid.
+    pub confirmation_token_ttl_minutes: u64,
+    /// Serve past issues to anybody at `/archive`.
+    pub public_archive: bool,
+}
+
+impl ApplicationSettings {
+    pub fn confirmation_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_token_ttl_minutes * 60)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -51,7 +214,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
     }
     
@@ -98,6 +261,14 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .add_source(
             config::File::from(configuration_directory.join(environment_filename))
         )
+        // Secrets come from the environment in production, e.g.
+        // `APP_APPLICATION__HMAC_SECRET` sets `application.hmac_secret`.
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true)
+        )
         .build()?;
     
     settings.try_deserialize::<Settings>()
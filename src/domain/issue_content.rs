@@ -0,0 +1,126 @@
+use crate::templating::{IssueContext, Template};
+
+#[derive(Debug)]
+pub struct IssueContent {
+    html: String,
+    text: String,
+}
+
+const MAX_LENGTH: usize = 100_000;
+const TEXT_WIDTH: usize = 78;
+
+impl IssueContent {
+    /// The plain text body is generated from the HTML one when missing.
+    pub fn parse(html: String, text: Option<String>) -> Result<IssueContent, String> {
+        if html.trim().is_empty() {
+            return Err("The HTML content of an issue cannot be empty.".into());
+        }
+        let text = match text.filter(|text| !text.trim().is_empty()) {
+            Some(text) => text,
+            None => html2text::from_read(html.as_bytes(), TEXT_WIDTH),
+        };
+        if text.trim().is_empty() {
+            return Err("The content of an issue cannot be empty.".into());
+        }
+        if html.chars().count() > MAX_LENGTH || text.chars().count() > MAX_LENGTH {
+            return Err(format!(
+                "The content of an issue cannot be longer than {} characters.",
+                MAX_LENGTH
+            ));
+        }
+        if let Err(e) = Template::parse(&html, IssueContext::VARIABLES) {
+            return Err(format!("The HTML content is not a valid template: {}", e));
+        }
+        if let Err(e) = Template::parse(&text, IssueContext::VARIABLES) {
+            return Err(format!("The text content is not a valid template: {}", e));
+        }
+        Ok(Self { html, text })
+    }
+
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::IssueContent;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn the_given_text_is_kept() {
+        let content = IssueContent::parse(
+            "<p>Hello</p>".to_string(),
+            Some("Hello as text".to_string()),
+        )
+        .unwrap();
+        assert_eq!(content.html(), "<p>Hello</p>");
+        assert_eq!(content.text(), "Hello as text");
+    }
+
+    #[test]
+    fn missing_text_is_generated_from_the_html() {
+        let content = IssueContent::parse(
+            r#"<h1>News</h1><p>Read <a href="https://example.com">this</a> &amp; more.</p>"#
+                .to_string(),
+            None,
+        )
+        .unwrap();
+        assert!(content.text().contains("News"));
+        assert!(content.text().contains("Read [this][1] & more."));
+        assert!(content.text().contains("[1]: https://example.com"));
+        assert!(!content.text().contains('<'));
+    }
+
+    #[test]
+    fn blank_text_is_generated_from_the_html() {
+        let content = IssueContent::parse("<p>Hello</p>".to_string(), Some(" ".to_string()));
+        assert_eq!(content.unwrap().text().trim(), "Hello");
+    }
+
+    #[test]
+    fn placeholders_survive_text_generation() {
+        let content = IssueContent::parse(
+            r#"<p>Hi {{name}}</p><a href="{{unsubscribe_url}}">Unsubscribe</a>"#.to_string(),
+            None,
+        )
+        .unwrap();
+        assert!(content.text().contains("Hi {{name}}"));
+        assert!(content.text().contains("{{unsubscribe_url}}"));
+    }
+
+    #[test]
+    fn content_with_template_syntax_errors_is_rejected() {
+        assert_err!(IssueContent::parse(
+            "<p>Hi {{name}}</p>".to_string(),
+            Some("Hi {{ nickname }}".to_string())
+        ));
+        assert_err!(IssueContent::parse("<p>Hi {{name</p>".to_string(), None));
+    }
+
+    #[test]
+    fn empty_html_is_rejected() {
+        assert_err!(IssueContent::parse(" ".to_string(), Some("Hello".to_string())));
+    }
+
+    #[test]
+    fn html_without_any_text_is_rejected_when_text_is_missing() {
+        assert_err!(IssueContent::parse("<p></p>".to_string(), None));
+    }
+
+    #[test]
+    fn content_above_the_length_limit_is_rejected() {
+        let html = format!("<p>{}</p>", "a".repeat(100_000));
+        assert_err!(IssueContent::parse(html, Some("Hello".to_string())));
+    }
+
+    #[test]
+    fn content_at_the_length_limit_is_accepted() {
+        let text = "a".repeat(100_000);
+        assert_ok!(IssueContent::parse("<p>Hello</p>".to_string(), Some(text)));
+    }
+}
@@ -0,0 +1,14 @@
+/// Where an issue stands between being written and being sent.
+///
+/// Drafts are published either straight away or at a scheduled time, and
+/// scheduled issues can be cancelled until they go out. Stored as text in
+/// `newsletter_issues.status`, whose CHECK constraint lists the same values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    Published,
+    Cancelled,
+}
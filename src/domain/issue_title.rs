@@ -0,0 +1,79 @@
+use crate::templating::{IssueContext, Template};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug)]
+pub struct IssueTitle(String);
+
+const MAX_LENGTH: usize = 200;
+
+impl IssueTitle {
+    /// Titles end up in the subject line, hence the tight length limit.
+    pub fn parse(s: String) -> Result<IssueTitle, String> {
+        if s.trim().is_empty() {
+            return Err("The title of an issue cannot be empty.".into());
+        }
+        if s.graphemes(true).count() > MAX_LENGTH {
+            return Err(format!(
+                "The title of an issue cannot be longer than {} characters.",
+                MAX_LENGTH
+            ));
+        }
+        if s.contains(['\r', '\n']) {
+            return Err("The title of an issue cannot span multiple lines.".into());
+        }
+        if let Err(e) = Template::parse(&s, IssueContext::VARIABLES) {
+            return Err(format!("The title is not a valid template: {}", e));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for IssueTitle {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::IssueTitle;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_200_grapheme_long_title_is_valid() {
+        let title = "ё".repeat(200);
+        assert_ok!(IssueTitle::parse(title));
+    }
+
+    #[test]
+    fn a_title_longer_than_200_graphemes_is_rejected() {
+        let title = "a".repeat(201);
+        assert_err!(IssueTitle::parse(title));
+    }
+
+    #[test]
+    fn whitespace_only_titles_are_rejected() {
+        assert_err!(IssueTitle::parse(" ".to_string()));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(IssueTitle::parse("".to_string()));
+    }
+
+    #[test]
+    fn titles_spanning_multiple_lines_are_rejected() {
+        assert_err!(IssueTitle::parse("Issue\r\nBcc: someone".to_string()));
+    }
+
+    #[test]
+    fn titles_with_template_syntax_errors_are_rejected() {
+        assert_err!(IssueTitle::parse("News for {{ name".to_string()));
+    }
+
+    #[test]
+    fn a_valid_title_is_parsed_successfully() {
+        assert_ok!(IssueTitle::parse("Issue #1: (Almost) done".to_string()));
+        assert_ok!(IssueTitle::parse("Issue #2, for {{name}}".to_string()));
+    }
+}
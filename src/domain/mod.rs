@@ -1,7 +1,19 @@
 mod subscriber_name;
 mod subscriber_email;
+mod subscriber_locale;
+mod subscriber_status;
 mod new_subscriber;
+mod unsubscribe_token;
+mod issue_title;
+mod issue_content;
+mod issue_status;
 
 pub use subscriber_name::SubscriberName;
 pub use subscriber_email::SubscriberEmail;
+pub use subscriber_locale::SubscriberLocale;
+pub use subscriber_status::{InvalidStatusTransition, SubscriberStatus};
 pub use new_subscriber::NewSubscriber;
+pub use unsubscribe_token::UnsubscribeToken;
+pub use issue_title::IssueTitle;
+pub use issue_content::IssueContent;
+pub use issue_status::IssueStatus;
@@ -1,7 +1,10 @@
 use crate::domain::SubscriberName;
 use crate::domain::SubscriberEmail;
+use crate::domain::SubscriberLocale;
 
+#[derive(Debug)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name : SubscriberName,
+    pub locale: Option<SubscriberLocale>,
 }
\ No newline at end of file
@@ -0,0 +1,55 @@
+/// A BCP 47-like language tag such as `en` or `pt-br`, normalized to lower
+/// case. Tags without a translation fall back when rendering emails.
+#[derive(Debug)]
+pub struct SubscriberLocale(String);
+
+impl SubscriberLocale {
+    pub fn parse(s: String) -> Result<SubscriberLocale, String> {
+        let locale = s.trim().to_lowercase().replace('_', "-");
+        let mut subtags = locale.split('-');
+        let language_is_valid = subtags.next().is_some_and(|language| {
+            (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_lowercase())
+        });
+        let subtags_are_valid = subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+
+        if language_is_valid && subtags_are_valid && locale.len() <= 35 {
+            Ok(Self(locale))
+        } else {
+            Err(format!("{} is not a valid locale.", s))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberLocale {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberLocale;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn language_tags_are_accepted() {
+        for locale in ["en", "fr", "pt-br", "zh-hant-tw", "ast"] {
+            assert_ok!(SubscriberLocale::parse(locale.to_string()));
+        }
+    }
+
+    #[test]
+    fn locales_are_normalized() {
+        let locale = SubscriberLocale::parse("pt_BR".to_string()).unwrap();
+        assert_eq!(locale.as_ref(), "pt-br");
+    }
+
+    #[test]
+    fn invalid_locales_are_rejected() {
+        for locale in ["", "e", "english", "en-", "en-!!", "../en", "12"] {
+            assert_err!(SubscriberLocale::parse(locale.to_string()));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+/// Where a subscriber stands in the double opt-in flow for a list, as
+/// stored in `newsletter_subscriptions.status`.
+///
+/// Subscribers start out pending until they confirm, and may then leave,
+/// bounce or complain. Only leaving or bouncing can be undone, by going
+/// through the confirmation again; a spam complaint can be received at any
+/// point and is final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("A subscriber cannot go from `{from}` to `{to}`.")]
+pub struct InvalidStatusTransition {
+    pub from: SubscriberStatus,
+    pub to: SubscriberStatus,
+}
+
+impl SubscriberStatus {
+    pub const ALL: [SubscriberStatus; 5] = [
+        SubscriberStatus::PendingConfirmation,
+        SubscriberStatus::Confirmed,
+        SubscriberStatus::Unsubscribed,
+        SubscriberStatus::Bounced,
+        SubscriberStatus::Complained,
+    ];
+
+    /// The value stored in the `status` columns.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+            SubscriberStatus::Bounced => "bounced",
+            SubscriberStatus::Complained => "complained",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<SubscriberStatus, String> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid subscriber status.", s))
+    }
+
+    pub fn can_transition_to(self, next: SubscriberStatus) -> bool {
+        use SubscriberStatus::*;
+
+        matches!(
+            (self, next),
+            (PendingConfirmation, Confirmed | Unsubscribed | Bounced)
+                | (Confirmed, Unsubscribed | Bounced)
+                | (Unsubscribed | Bounced, PendingConfirmation)
+                | (
+                    PendingConfirmation | Confirmed | Unsubscribed | Bounced,
+                    Complained
+                )
+        )
+    }
+
+    pub fn transition_to(
+        self,
+        next: SubscriberStatus,
+    ) -> Result<SubscriberStatus, InvalidStatusTransition> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(InvalidStatusTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriberStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for SubscriberStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberStatus;
+    use crate::domain::SubscriberStatus::*;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn every_status_round_trips_through_its_stored_value() {
+        for status in SubscriberStatus::ALL {
+            assert_eq!(SubscriberStatus::parse(status.as_str()), Ok(status));
+        }
+        assert_err!(SubscriberStatus::parse("Confirmed"));
+        assert_err!(SubscriberStatus::parse(""));
+    }
+
+    #[test]
+    fn the_double_opt_in_flow_is_allowed() {
+        assert_ok!(PendingConfirmation.transition_to(Confirmed));
+        assert_ok!(Confirmed.transition_to(Unsubscribed));
+        assert_ok!(Unsubscribed.transition_to(PendingConfirmation));
+        assert_ok!(Confirmed.transition_to(Bounced));
+        assert_ok!(Bounced.transition_to(PendingConfirmation));
+        assert_ok!(Confirmed.transition_to(Complained));
+    }
+
+    #[test]
+    fn confirmation_cannot_be_skipped() {
+        assert_err!(Unsubscribed.transition_to(Confirmed));
+        assert_err!(Bounced.transition_to(Confirmed));
+        assert_err!(Confirmed.transition_to(PendingConfirmation));
+    }
+
+    #[test]
+    fn a_status_does_not_transition_to_itself() {
+        for status in SubscriberStatus::ALL {
+            assert_err!(status.transition_to(status));
+        }
+    }
+
+    #[test]
+    fn complaints_are_final() {
+        for status in SubscriberStatus::ALL {
+            assert!(!Complained.can_transition_to(status));
+        }
+    }
+
+    #[test]
+    fn complaints_are_accepted_in_any_other_status() {
+        for status in SubscriberStatus::ALL {
+            if status != Complained {
+                assert_ok!(status.transition_to(Complained));
+            }
+        }
+    }
+}
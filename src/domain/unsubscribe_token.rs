@@ -0,0 +1,114 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Identifies a subscriber's membership of a list in one-click unsubscribe
+/// links.
+///
+/// The token is the subscriber and list ids followed by an HMAC of them, so
+/// it cannot be forged for somebody else without knowing the application
+/// secret.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn for_subscriber(subscriber_id: Uuid, list_id: Uuid, secret: &Secret<String>) -> Self {
+        let signature = URL_SAFE_NO_PAD.encode(
+            Self::mac(subscriber_id, list_id, secret)
+                .finalize()
+                .into_bytes(),
+        );
+        Self(format!("{}.{}.{}", subscriber_id, list_id, signature))
+    }
+
+    /// Returns the ids of the subscriber and of the list the token was
+    /// issued for.
+    pub fn verify(token: &str, secret: &Secret<String>) -> Result<(Uuid, Uuid), String> {
+        let invalid = || format!("{} is not a valid unsubscribe token.", token);
+        let mut parts = token.splitn(3, '.');
+        let (Some(subscriber_id), Some(list_id), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid())?;
+        let list_id = Uuid::parse_str(list_id).map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        Self::mac(subscriber_id, list_id, secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        Ok((subscriber_id, list_id))
+    }
+
+    fn mac(subscriber_id: Uuid, list_id: Uuid, secret: &Secret<String>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(b"unsubscribe:");
+        mac.update(subscriber_id.as_bytes());
+        mac.update(list_id.as_bytes());
+        mac
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_token_is_verified_back_to_its_subscriber_and_list() {
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        let token = UnsubscribeToken::for_subscriber(subscriber_id, list_id, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret()),
+            (subscriber_id, list_id)
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::for_subscriber(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &Secret::new("another-secret".to_string()),
+        );
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_token_for_a_different_subscriber_or_list_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        let token = UnsubscribeToken::for_subscriber(subscriber_id, list_id, &secret());
+        let signature = token.as_ref().rsplit('.').next().unwrap();
+
+        let other_subscriber = format!("{}.{}.{}", Uuid::new_v4(), list_id, signature);
+        let other_list = format!("{}.{}.{}", subscriber_id, Uuid::new_v4(), signature);
+
+        assert_err!(UnsubscribeToken::verify(&other_subscriber, &secret()));
+        assert_err!(UnsubscribeToken::verify(&other_list, &secret()));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "not-a-token", "not-a-uuid.not-a-uuid.c2lnbmF0dXJl"] {
+            assert_err!(UnsubscribeToken::verify(token, &secret()));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use super::{to_message, Email, EmailTransport};
+use anyhow::Context;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Appends every email to a local mbox file instead of sending it.
+/// Meant for local development.
+pub struct FileSinkTransport {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileSinkTransport {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileSinkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = to_message(email)?.formatted();
+        let message = String::from_utf8_lossy(&message);
+
+        let mut entry = format!(
+            "From {} {}\n",
+            email.from.as_ref(),
+            chrono::Utc::now().format("%a %b %e %H:%M:%S %Y")
+        );
+        for line in message.lines() {
+            // mboxrd quoting, so that the body cannot start a new message.
+            if line.trim_start_matches('>').starts_with("From ") {
+                entry.push('>');
+            }
+            entry.push_str(line);
+            entry.push('\n');
+        }
+        entry.push('\n');
+
+        let _guard = self.lock.lock().await;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create the mbox directory.")?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context("Failed to open the mbox file.")?;
+        file.write_all(entry.as_bytes())
+            .await
+            .context("Failed to append the email to the mbox file.")?;
+        // tokio completes writes in the background: make sure this one has
+        // landed before reporting success.
+        file.flush()
+            .await
+            .context("Failed to flush the mbox file.")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, FileSinkTransport};
+    use claims::assert_ok;
+
+    #[tokio::test]
+    async fn send_email_appends_messages_to_the_mbox_file() {
+        let path = std::env::temp_dir()
+            .join(uuid::Uuid::new_v4().to_string())
+            .join("emails.mbox");
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            FileSinkTransport::new(path.clone()),
+        );
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        assert_ok!(
+            email_client
+                .send_email(&recipient, "First", "<p>First</p>", "From the top")
+                .await
+        );
+        assert_ok!(
+            email_client
+                .send_email(&recipient, "Second", "<p>Second</p>", "Second")
+                .await
+        );
+
+        let mbox = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(mbox.matches("\nFrom sender@example.com ").count() + 1, 2);
+        assert!(mbox.starts_with("From sender@example.com "));
+        assert!(mbox.contains(">From the top"));
+        assert!(mbox.contains("Subject: Second"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
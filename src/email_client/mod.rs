@@ -0,0 +1,100 @@
+mod file_sink;
+mod postmark;
+mod smtp;
+
+pub use file_sink::FileSinkTransport;
+pub use postmark::{PostmarkTransport, RetryPolicy};
+pub use smtp::SmtpTransport;
+
+use crate::domain::SubscriberEmail;
+use std::sync::Arc;
+
+/// A fully assembled email, ready to be handed over to a transport.
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    /// Additional headers, e.g. `List-Unsubscribe`.
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+/// The mechanism used to get an email out of the door.
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Clone)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Arc<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(
+        sender: SubscriberEmail,
+        transport: impl EmailTransport + 'static,
+    ) -> Self {
+        Self {
+            sender,
+            transport: Arc::new(transport),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), anyhow::Error> {
+        let email = Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+        self.transport.send(&email).await
+    }
+}
+
+/// Renders `email` as an RFC 5322 message with both a plain text and an
+/// HTML alternative.
+fn to_message(email: &Email<'_>) -> Result<lettre::Message, anyhow::Error> {
+    use anyhow::Context;
+    use lettre::message::header::{HeaderName, HeaderValue};
+    use lettre::message::MultiPart;
+
+    let mut builder = lettre::Message::builder()
+        .from(email.from.as_ref().parse().context("Invalid sender address.")?)
+        .to(email.to.as_ref().parse().context("Invalid recipient address.")?)
+        .subject(email.subject);
+    for (name, value) in email.headers {
+        let name = HeaderName::new_from_ascii(name.to_string())
+            .context("Invalid header name.")?;
+        builder = builder.raw_header(HeaderValue::new(name, value.to_string()));
+    }
+    let message = builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_body.to_string(),
+            email.html_body.to_string(),
+        ))
+        .context("Failed to build the email message.")?;
+    Ok(message)
+}
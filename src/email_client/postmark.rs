@@ -0,0 +1,492 @@
+use super::{Email, EmailTransport};
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Response, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+/// Sends emails through Postmark's HTTP API.
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+/// How `PostmarkTransport` retries requests that failed for transient reasons:
+/// timeouts, connection errors or one of `retryable_status_codes`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Pick a random delay between zero and the exponential backoff.
+    pub jitter: bool,
+    pub retryable_status_codes: Vec<u16>,
+    pub honor_retry_after: bool,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: false,
+            retryable_status_codes: vec![],
+            honor_retry_after: false,
+        }
+    }
+
+    fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+    }
+
+    fn is_retryable_error(&self, e: &reqwest::Error) -> bool {
+        e.is_timeout() || e.is_connect()
+    }
+
+    /// Delay before the next attempt, `n_failed_attempts` being the number
+    /// of attempts made so far.
+    fn delay(&self, n_failed_attempts: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after.filter(|_| self.honor_retry_after) {
+            return retry_after.min(self.max_delay);
+        }
+        let exponent = n_failed_attempts.saturating_sub(1).min(31);
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_delay);
+        if self.jitter {
+            let millis = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64);
+            Duration::from_millis(millis)
+        } else {
+            backoff
+        }
+    }
+}
+
+/// Parses a `Retry-After` header given either as a number of seconds or
+/// as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String, 
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap();
+            
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+            retry_policy,
+        }
+    }
+    
+    async fn send_request(&self, email: &Email<'_>) -> Result<(), reqwest::Error> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email
+                .headers
+                .iter()
+                .map(|(name, value)| Header { name, value })
+                .collect(),
+        };
+        
+        let mut n_attempts = 0;
+        loop {
+            n_attempts += 1;
+            let outcome = self
+                .http_client
+                .post(&url)
+                .header(
+                    "X-Postmark-Server-Token",
+                    self.authorization_token.expose_secret()
+                )
+                .json(&request_body)
+                .send()
+                .await;
+            let is_last_attempt = n_attempts >= self.retry_policy.max_attempts;
+            let delay = match outcome {
+                Ok(response) => {
+                    if is_last_attempt
+                        || !self.retry_policy.is_retryable_status(response.status())
+                    {
+                        response.error_for_status()?;
+                        return Ok(());
+                    }
+                    tracing::warn!(
+                        status = %response.status(),
+                        n_attempts,
+                        "Email delivery failed with a retryable status code"
+                    );
+                    self.retry_policy.delay(n_attempts, retry_after(&response))
+                }
+                Err(e) => {
+                    if is_last_attempt || !self.retry_policy.is_retryable_error(&e) {
+                        return Err(e);
+                    }
+                    tracing::warn!(
+                        error.message = %e,
+                        n_attempts,
+                        "Email delivery failed with a retryable error"
+                    );
+                    self.retry_policy.delay(n_attempts, None)
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        Ok(self.send_request(email).await?)
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, PostmarkTransport, RetryPolicy};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::*;
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> =
+                serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url(){
+        let mock_server: MockServer   = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        
+        
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        
+    }
+    
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200(){
+        let mock_server: MockServer   = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_500(){
+        let mock_server: MockServer   = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long(){
+        let mock_server: MockServer   = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        
+        let response = ResponseTemplate::new(200)
+            .set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+    
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+    
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+    
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+    
+    fn email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url, 
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy::none()
+        );
+        EmailClient::new(email(), transport)
+    }
+
+    fn retrying_email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts: 3,
+                base_delay: std::time::Duration::from_millis(10),
+                max_delay: std::time::Duration::from_secs(2),
+                jitter: true,
+                retryable_status_codes: vec![429, 500, 503],
+                honor_retry_after: true,
+            }
+        );
+        EmailClient::new(email(), transport)
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_a_retryable_status_code(){
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_max_attempts(){
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_a_non_retryable_status_code(){
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_after_a_timeout(){
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_secs(180))
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_honors_retry_after(){
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "1")
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let start = std::time::Instant::now();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_max_delay(){
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(500),
+            jitter: false,
+            retryable_status_codes: vec![],
+            honor_retry_after: true,
+        };
+
+        assert_eq!(policy.delay(1, None).as_millis(), 100);
+        assert_eq!(policy.delay(2, None).as_millis(), 200);
+        assert_eq!(policy.delay(3, None).as_millis(), 400);
+        assert_eq!(policy.delay(4, None).as_millis(), 500);
+        assert_eq!(
+            policy.delay(1, Some(std::time::Duration::from_secs(60))).as_millis(),
+            500
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server: MockServer = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,102 @@
+use super::{EmailJob, PgTransaction, SendOutcome};
+use crate::domain::SubscriberStatus;
+use crate::email_client::EmailClient;
+use crate::email_templates::{get_recipient, EmailTemplates};
+use crate::lists::get_membership_status;
+use crate::routes::{generate_subscription_token, send_confirmation_email, store_token};
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The link asked for by a signup. Every signup queues one, so that
+/// answering it takes as long whatever we know about the address.
+pub struct ConfirmationEmail {
+    pub email_client: EmailClient,
+    pub templates: EmailTemplates,
+    pub base_url: String,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ConfirmationTask {
+    subscriber_id: Uuid,
+    newsletter_id: Uuid,
+    locale: Option<String>,
+}
+
+/// Queues a confirmation email, in the transaction that records the signup.
+/// A newer request for the same list replaces the requested locale, unless
+/// it does not ask for one.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    requested_locale: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscriber_id, newsletter_id, locale)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (subscriber_id, newsletter_id) DO UPDATE
+        SET locale = COALESCE(EXCLUDED.locale, confirmation_email_queue.locale)
+        "#,
+        subscriber_id,
+        list_id,
+        requested_locale,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl EmailJob for ConfirmationEmail {
+    type Task = ConfirmationTask;
+
+    const QUEUE: &'static str = "confirmation_email_queue";
+    const NAME: &'static str = "confirmation email";
+
+    /// Sends a fresh link to a member who is still pending; those who are
+    /// not have nothing to confirm.
+    #[tracing::instrument(
+        skip_all,
+        fields(subscriber_id = %task.subscriber_id, list_id = %task.newsletter_id)
+    )]
+    async fn send(
+        &self,
+        transaction: &mut PgTransaction,
+        task: &ConfirmationTask,
+    ) -> Result<SendOutcome, anyhow::Error> {
+        let (subscriber_id, list_id) = (task.subscriber_id, task.newsletter_id);
+        let status = get_membership_status(transaction, list_id, subscriber_id).await?;
+        if status != Some(SubscriberStatus::PendingConfirmation) {
+            tracing::info!("Skipping a member who has nothing to confirm.");
+            return Ok(SendOutcome::Done);
+        }
+        let mut recipient = get_recipient(&mut **transaction, subscriber_id, list_id).await?;
+        if let Some(locale) = &task.locale {
+            recipient.locale = Some(locale.clone());
+        }
+
+        let subscription_token = generate_subscription_token();
+        let outcome = send_confirmation_email(
+            &self.email_client,
+            &self.templates,
+            &recipient,
+            &self.base_url,
+            &subscription_token,
+        )
+        .await;
+        if let Err(e) = outcome {
+            return Ok(SendOutcome::Failed(e));
+        }
+        // The link only works once it has been sent.
+        store_token(
+            transaction,
+            subscriber_id,
+            list_id,
+            &subscription_token,
+            task.locale.as_deref(),
+        )
+        .await?;
+        Ok(SendOutcome::Done)
+    }
+}
@@ -0,0 +1,166 @@
+//! Sends the transactional emails queued by request handlers.
+//!
+//! Handlers only queue the email, in the transaction that records what
+//! prompted it, so that answering them does not depend on whether one is
+//! sent, or on how long the email API takes. Every kind of email has its own
+//! table, with a `task_id`, `n_retries` and `execute_after` next to whatever
+//! an [`EmailJob`] needs to send it.
+
+mod confirmation;
+mod personal_data;
+mod unsubscribe;
+mod welcome;
+
+pub use confirmation::{enqueue_confirmation_email, ConfirmationEmail};
+pub use personal_data::{enqueue_personal_data_email, PersonalDataEmail};
+pub use unsubscribe::{enqueue_unsubscribe_email, UnsubscribeAcknowledgement};
+pub use welcome::{enqueue_welcome_email, WelcomeEmail};
+
+use crate::issue_delivery_worker::{retry_delay_seconds, ExecutionOutcome};
+use sqlx::postgres::PgRow;
+use sqlx::{Executor, FromRow, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::{field::display, Span};
+
+/// Attempts, including the first one, before a queued email is dropped.
+const MAX_ATTEMPTS: i16 = 5;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// What came of a task, database errors aside.
+pub enum SendOutcome {
+    /// The email was sent, or no longer needs to be.
+    Done,
+    /// The email could not be sent, which is worth another try.
+    Failed(anyhow::Error),
+}
+
+/// A kind of email, queued in its own table.
+#[async_trait::async_trait]
+pub trait EmailJob: Send + Sync + 'static {
+    /// A row of the queue.
+    type Task: for<'r> FromRow<'r, PgRow> + Send + Sync + Unpin;
+
+    /// The table the tasks are queued in.
+    const QUEUE: &'static str;
+    /// What the email is called in logs.
+    const NAME: &'static str;
+
+    /// Sends the email asked for by `task`. Whatever else is done through
+    /// `transaction` is committed once the task has been dealt with.
+    async fn send(
+        &self,
+        transaction: &mut PgTransaction,
+        task: &Self::Task,
+    ) -> Result<SendOutcome, anyhow::Error>;
+}
+
+#[derive(sqlx::FromRow)]
+struct QueuedTask<T> {
+    task_id: i64,
+    n_retries: i16,
+    #[sqlx(flatten)]
+    task: T,
+}
+
+pub async fn run_email_worker_until_stopped<J: EmailJob>(
+    pool: PgPool,
+    job: J,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_send_queued_email(&pool, &job).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(queue = J::QUEUE, task_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_send_queued_email<J: EmailJob>(
+    pool: &PgPool,
+    job: &J,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let query = format!(
+        "SELECT * FROM {} WHERE execute_after <= now() FOR UPDATE SKIP LOCKED LIMIT 1",
+        J::QUEUE
+    );
+    let task = sqlx::query_as::<_, QueuedTask<J::Task>>(&query)
+        .fetch_optional(&mut *transaction)
+        .await?;
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("task_id", display(task.task_id));
+
+    match job.send(&mut transaction, &task.task).await? {
+        SendOutcome::Done => delete_task::<J>(transaction, task.task_id).await?,
+        SendOutcome::Failed(e) if task.n_retries + 1 >= MAX_ATTEMPTS => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a queued {}. Giving up.",
+                J::NAME
+            );
+            delete_task::<J>(transaction, task.task_id).await?;
+        }
+        SendOutcome::Failed(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a queued {}. Retrying later.",
+                J::NAME
+            );
+            retry_later::<J>(transaction, task.task_id, task.n_retries).await?;
+        }
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn delete_task<J: EmailJob>(
+    mut transaction: PgTransaction,
+    task_id: i64,
+) -> Result<(), anyhow::Error> {
+    let query = format!("DELETE FROM {} WHERE task_id = $1", J::QUEUE);
+    transaction
+        .execute(sqlx::query(&query).bind(task_id))
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn retry_later<J: EmailJob>(
+    mut transaction: PgTransaction,
+    task_id: i64,
+    n_retries: i16,
+) -> Result<(), anyhow::Error> {
+    let query = format!(
+        r#"
+        UPDATE {}
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $2)
+        WHERE task_id = $1
+        "#,
+        J::QUEUE
+    );
+    transaction
+        .execute(
+            sqlx::query(&query)
+                .bind(task_id)
+                .bind(retry_delay_seconds(n_retries)),
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
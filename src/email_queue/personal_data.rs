@@ -0,0 +1,106 @@
+use super::{EmailJob, PgTransaction, SendOutcome};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_templates::{get_recipient, EmailTemplate, EmailTemplates};
+use crate::lists::resolve_list_id;
+use crate::personal_data::hash_token;
+use crate::routes::generate_subscription_token;
+use anyhow::Context;
+use sqlx::{Executor, PgExecutor};
+
+/// The answer to a personal data request. Requests are queued whatever the
+/// address, so that answering them takes the same time whether or not it
+/// belongs to a subscriber; those that do not are dropped here.
+pub struct PersonalDataEmail {
+    pub email_client: EmailClient,
+    pub templates: EmailTemplates,
+    pub base_url: String,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct PersonalDataTask {
+    email: String,
+}
+
+/// Queues the email answering a personal data request.
+#[tracing::instrument(skip(executor))]
+pub async fn enqueue_personal_data_email(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO personal_data_email_queue (email)
+        VALUES ($1)
+        ON CONFLICT DO NOTHING
+        "#,
+        email.as_ref(),
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl EmailJob for PersonalDataEmail {
+    type Task = PersonalDataTask;
+
+    const QUEUE: &'static str = "personal_data_email_queue";
+    const NAME: &'static str = "personal data email";
+
+    /// Emails a link to download, and one to erase, everything we hold
+    /// about the subscriber with exactly the requested address. Addresses
+    /// are unique only as written, so two subscribers may differ in case
+    /// alone.
+    #[tracing::instrument(skip_all, fields(subscriber_id = tracing::field::Empty))]
+    async fn send(
+        &self,
+        transaction: &mut PgTransaction,
+        task: &PersonalDataTask,
+    ) -> Result<SendOutcome, anyhow::Error> {
+        let subscriber_id = sqlx::query!(
+            r#"SELECT id FROM subscriptions WHERE email = $1"#,
+            task.email
+        )
+        .fetch_optional(&mut **transaction)
+        .await?
+        .map(|r| r.id);
+        let Some(subscriber_id) = subscriber_id else {
+            tracing::info!("Skipping a personal data request for an unknown address.");
+            return Ok(SendOutcome::Done);
+        };
+        tracing::Span::current().record("subscriber_id", tracing::field::display(subscriber_id));
+        // Only used for the name of the newsletter in the email.
+        let list_id = resolve_list_id(&mut **transaction, None)
+            .await?
+            .context("There is no default newsletter list.")?;
+        let recipient = get_recipient(&mut **transaction, subscriber_id, list_id).await?;
+
+        let token = generate_subscription_token();
+        let data_link = format!("{}/subscriptions/personal-data?token={}", self.base_url, token);
+        let erasure_link = format!(
+            "{}/subscriptions/personal-data/erase?token={}",
+            self.base_url, token
+        );
+        let outcome = self
+            .templates
+            .send(
+                &self.email_client,
+                EmailTemplate::PersonalData,
+                &recipient,
+                &[("data_link", &data_link), ("erasure_link", &erasure_link)],
+            )
+            .await;
+        if let Err(e) = outcome {
+            return Ok(SendOutcome::Failed(e));
+        }
+        // The link only works once it has been sent.
+        let query = sqlx::query!(
+            r#"INSERT INTO personal_data_tokens (token_hash, subscriber_id) VALUES ($1, $2)"#,
+            hash_token(&token),
+            subscriber_id,
+        );
+        transaction.execute(query).await?;
+        Ok(SendOutcome::Done)
+    }
+}
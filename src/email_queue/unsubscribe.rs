@@ -0,0 +1,79 @@
+use super::{EmailJob, PgTransaction, SendOutcome};
+use crate::domain::SubscriberStatus;
+use crate::email_client::EmailClient;
+use crate::email_templates::{get_recipient, EmailTemplate, EmailTemplates};
+use crate::lists::get_membership_status;
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+pub struct UnsubscribeAcknowledgement {
+    pub email_client: EmailClient,
+    pub templates: EmailTemplates,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct UnsubscribeTask {
+    subscriber_id: Uuid,
+    newsletter_id: Uuid,
+}
+
+/// Queues an unsubscribe acknowledgement, sent by the worker after the
+/// unsubscription has been committed so that one-click requests do not
+/// wait on the email API.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_unsubscribe_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO unsubscribe_email_queue (subscriber_id, newsletter_id)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        list_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl EmailJob for UnsubscribeAcknowledgement {
+    type Task = UnsubscribeTask;
+
+    const QUEUE: &'static str = "unsubscribe_email_queue";
+    const NAME: &'static str = "unsubscribe acknowledgement";
+
+    #[tracing::instrument(
+        skip_all,
+        fields(subscriber_id = %task.subscriber_id, list_id = %task.newsletter_id)
+    )]
+    async fn send(
+        &self,
+        transaction: &mut PgTransaction,
+        task: &UnsubscribeTask,
+    ) -> Result<SendOutcome, anyhow::Error> {
+        let (subscriber_id, list_id) = (task.subscriber_id, task.newsletter_id);
+        let status = get_membership_status(transaction, list_id, subscriber_id).await?;
+        if status != Some(SubscriberStatus::Unsubscribed) {
+            tracing::info!("Skipping a subscriber who is no longer unsubscribed.");
+            return Ok(SendOutcome::Done);
+        }
+        let recipient = get_recipient(&mut **transaction, subscriber_id, list_id).await?;
+        let outcome = self
+            .templates
+            .send(
+                &self.email_client,
+                EmailTemplate::UnsubscribeAcknowledgement,
+                &recipient,
+                &[],
+            )
+            .await;
+        Ok(match outcome {
+            Ok(()) => SendOutcome::Done,
+            Err(e) => SendOutcome::Failed(e),
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use super::{EmailJob, PgTransaction, SendOutcome};
+use crate::domain::SubscriberStatus;
+use crate::email_client::EmailClient;
+use crate::email_templates::{get_recipient, EmailTemplate, EmailTemplates};
+use crate::issue_delivery_worker::unsubscribe_link;
+use crate::lists::get_membership_status;
+use secrecy::Secret;
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+pub struct WelcomeEmail {
+    pub email_client: EmailClient,
+    pub templates: EmailTemplates,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct WelcomeTask {
+    subscriber_id: Uuid,
+    newsletter_id: Uuid,
+}
+
+/// Queues a welcome email, sent by the worker after the confirmation has
+/// been committed so that email failures cannot undo it.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_welcome_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO welcome_email_queue (subscriber_id, newsletter_id)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        list_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl EmailJob for WelcomeEmail {
+    type Task = WelcomeTask;
+
+    const QUEUE: &'static str = "welcome_email_queue";
+    const NAME: &'static str = "welcome email";
+
+    #[tracing::instrument(
+        skip_all,
+        fields(subscriber_id = %task.subscriber_id, list_id = %task.newsletter_id)
+    )]
+    async fn send(
+        &self,
+        transaction: &mut PgTransaction,
+        task: &WelcomeTask,
+    ) -> Result<SendOutcome, anyhow::Error> {
+        let (subscriber_id, list_id) = (task.subscriber_id, task.newsletter_id);
+        let status = get_membership_status(transaction, list_id, subscriber_id).await?;
+        if status != Some(SubscriberStatus::Confirmed) {
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+            return Ok(SendOutcome::Done);
+        }
+        let recipient = get_recipient(&mut **transaction, subscriber_id, list_id).await?;
+        let unsubscribe_link =
+            unsubscribe_link(&self.base_url, subscriber_id, list_id, &self.hmac_secret);
+        let outcome = self
+            .templates
+            .send(
+                &self.email_client,
+                EmailTemplate::Welcome,
+                &recipient,
+                &[("unsubscribe_url", &unsubscribe_link)],
+            )
+            .await;
+        Ok(match outcome {
+            Ok(()) => SendOutcome::Done,
+            Err(e) => SendOutcome::Failed(e),
+        })
+    }
+}
@@ -0,0 +1,421 @@
+//! Transactional emails, rendered from the files under `templates/emails`.
+//!
+//! Every email is made of three files per locale, `<name>.subject.txt`,
+//! `<name>.html` and `<name>.txt`, stored in a directory named after the
+//! locale. The files are embedded at compile time; a directory with the same
+//! layout can be configured to override any of them without a rebuild.
+
+use crate::configuration::EmailTemplateSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::templating::Template;
+use anyhow::Context;
+use sqlx::PgExecutor;
+use std::collections::HashMap;
+use std::path::Path;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmailTemplate {
+    Confirmation,
+    Welcome,
+    UnsubscribeAcknowledgement,
+    PersonalData,
+}
+
+impl EmailTemplate {
+    const ALL: [EmailTemplate; 4] = [
+        EmailTemplate::Confirmation,
+        EmailTemplate::Welcome,
+        EmailTemplate::UnsubscribeAcknowledgement,
+        EmailTemplate::PersonalData,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            EmailTemplate::Confirmation => "confirmation",
+            EmailTemplate::Welcome => "welcome",
+            EmailTemplate::UnsubscribeAcknowledgement => "unsubscribed",
+            EmailTemplate::PersonalData => "personal_data",
+        }
+    }
+
+    /// The placeholders each template may use. `name` and `list_name` are
+    /// always filled in from the [`Recipient`].
+    fn variables(self) -> &'static [&'static str] {
+        match self {
+            EmailTemplate::Confirmation => &["name", "list_name", "confirmation_link"],
+            EmailTemplate::Welcome => &["name", "list_name", "unsubscribe_url"],
+            EmailTemplate::UnsubscribeAcknowledgement => &["name", "list_name"],
+            EmailTemplate::PersonalData => &["name", "list_name", "data_link", "erasure_link"],
+        }
+    }
+}
+
+const PARTS: [&str; 3] = ["subject.txt", "html", "txt"];
+
+macro_rules! embed_templates {
+    ($($locale:literal => [$($name:literal),*]),* $(,)?) => {
+        &[$($(
+            (
+                concat!($locale, "/", $name, ".subject.txt"),
+                include_str!(concat!(
+                    env!("CARGO_MANIFEST_DIR"), "/templates/emails/",
+                    $locale, "/", $name, ".subject.txt"
+                )),
+            ),
+            (
+                concat!($locale, "/", $name, ".html"),
+                include_str!(concat!(
+                    env!("CARGO_MANIFEST_DIR"), "/templates/emails/",
+                    $locale, "/", $name, ".html"
+                )),
+            ),
+            (
+                concat!($locale, "/", $name, ".txt"),
+                include_str!(concat!(
+                    env!("CARGO_MANIFEST_DIR"), "/templates/emails/",
+                    $locale, "/", $name, ".txt"
+                )),
+            ),
+        )*)*]
+    };
+}
+
+/// Keyed by `<locale>/<file name>`.
+const EMBEDDED: &[(&str, &str)] = embed_templates![
+    "en" => ["confirmation", "welcome", "unsubscribed", "personal_data"],
+    "fr" => ["confirmation", "welcome", "unsubscribed", "personal_data"],
+];
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+/// The details every transactional email is addressed with.
+pub struct Recipient {
+    pub email: SubscriberEmail,
+    pub name: String,
+    /// Falls back to the default locale if missing.
+    pub locale: Option<String>,
+    pub list_name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailTemplates {
+    /// Keyed by `<locale>/<file name>`, like [`EMBEDDED`].
+    files: HashMap<String, String>,
+    /// Take precedence over the subject files in every locale.
+    subjects: HashMap<EmailTemplate, String>,
+    default_locale: String,
+}
+
+impl EmailTemplates {
+    /// Fails if an override cannot be read, or if any template is invalid,
+    /// so that mistakes surface at startup rather than when sending.
+    pub fn load(settings: &EmailTemplateSettings) -> Result<Self, anyhow::Error> {
+        let mut files: HashMap<String, String> = EMBEDDED
+            .iter()
+            .map(|(key, content)| (key.to_string(), content.to_string()))
+            .collect();
+        if let Some(directory) = &settings.override_directory {
+            load_overrides(Path::new(directory), &mut files)?;
+        }
+        let templates = Self {
+            files,
+            subjects: HashMap::new(),
+            default_locale: normalize_locale(&settings.default_locale),
+        };
+        templates.validate()?;
+        Ok(templates)
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        for template in EmailTemplate::ALL {
+            for part in PARTS {
+                let key = format!("{}/{}.{}", self.default_locale, template.name(), part);
+                if !self.files.contains_key(&key) {
+                    anyhow::bail!("The default locale is missing the `{}` template.", key);
+                }
+            }
+        }
+        for (key, content) in &self.files {
+            let template = EmailTemplate::ALL
+                .into_iter()
+                .find(|t| file_name(key).starts_with(&format!("{}.", t.name())))
+                .with_context(|| format!("`{}` is not a known email template.", key))?;
+            Template::parse(content, template.variables())
+                .with_context(|| format!("The `{}` email template is invalid.", key))?;
+        }
+        Ok(())
+    }
+
+    /// Uses `subject` for `template` whatever the recipient's locale.
+    pub fn set_subject(
+        &mut self,
+        template: EmailTemplate,
+        subject: &str,
+    ) -> Result<(), anyhow::Error> {
+        Template::parse(subject, template.variables())
+            .with_context(|| format!("The `{}` email subject is invalid.", template.name()))?;
+        self.subjects.insert(template, subject.to_string());
+        Ok(())
+    }
+
+    /// Picks the most specific file available for the locale, e.g. `pt-br`,
+    /// then `pt`, then the default locale.
+    fn file(&self, template: EmailTemplate, part: &str, locale: Option<&str>) -> &str {
+        if let (Some(subject), "subject.txt") = (self.subjects.get(&template), part) {
+            return subject;
+        }
+        let locale = locale.map(normalize_locale);
+        let language = locale
+            .as_deref()
+            .and_then(|l| l.split_once('-'))
+            .map(|(language, _)| language);
+        locale
+            .as_deref()
+            .into_iter()
+            .chain(language)
+            .chain(std::iter::once(self.default_locale.as_str()))
+            .find_map(|locale| {
+                self.files
+                    .get(&format!("{}/{}.{}", locale, template.name(), part))
+            })
+            .map(String::as_str)
+            // `validate` made sure the default locale has every file.
+            .unwrap_or_default()
+    }
+
+    pub fn render(
+        &self,
+        template: EmailTemplate,
+        recipient: &Recipient,
+        values: &[(&str, &str)],
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let mut context = vec![
+            ("name", recipient.name.as_str()),
+            ("list_name", recipient.list_name.as_str()),
+        ];
+        context.extend_from_slice(values);
+        let locale = recipient.locale.as_deref();
+        let parse = |part| {
+            Template::parse(self.file(template, part, locale), template.variables())
+                .with_context(|| format!("The `{}` email template is invalid.", template.name()))
+        };
+        Ok(RenderedEmail {
+            subject: parse("subject.txt")?.render_text(context.as_slice()).trim().to_string(),
+            html_body: parse("html")?.render_html(context.as_slice()),
+            text_body: parse("txt")?.render_text(context.as_slice()),
+        })
+    }
+
+    pub async fn send(
+        &self,
+        email_client: &EmailClient,
+        template: EmailTemplate,
+        recipient: &Recipient,
+        values: &[(&str, &str)],
+    ) -> Result<(), anyhow::Error> {
+        let email = self.render(template, recipient, values)?;
+        email_client
+            .send_email(
+                &recipient.email,
+                &email.subject,
+                &email.html_body,
+                &email.text_body,
+            )
+            .await
+    }
+}
+
+#[tracing::instrument(name = "Get email recipient", skip(executor))]
+pub async fn get_recipient(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<Recipient, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT s.email, s.name, s.locale, n.name AS list_name
+        FROM subscriptions s, newsletters n
+        WHERE s.id = $1 AND n.newsletter_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to retrieve the recipient details.")?;
+    Ok(Recipient {
+        email: SubscriberEmail::parse(r.email).map_err(anyhow::Error::msg)?,
+        name: r.name,
+        locale: r.locale,
+        list_name: r.list_name,
+    })
+}
+
+fn load_overrides(
+    directory: &Path,
+    files: &mut HashMap<String, String>,
+) -> Result<(), anyhow::Error> {
+    let read_dir = |path: &Path| {
+        std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read the {} directory.", path.display()))
+    };
+    for locale in read_dir(directory)? {
+        let locale = locale?.path();
+        if !locale.is_dir() {
+            continue;
+        }
+        for file in read_dir(&locale)? {
+            let file = file?.path();
+            let key = format!(
+                "{}/{}",
+                normalize_locale(&file_name(&locale.to_string_lossy())),
+                file_name(&file.to_string_lossy())
+            );
+            let content = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}.", file.display()))?;
+            files.insert(key, content);
+        }
+    }
+    Ok(())
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_lowercase().replace('_', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailTemplate, EmailTemplates, Recipient};
+    use crate::configuration::EmailTemplateSettings;
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    fn settings(override_directory: Option<String>) -> EmailTemplateSettings {
+        EmailTemplateSettings {
+            override_directory,
+            default_locale: "en".into(),
+        }
+    }
+
+    fn recipient(locale: Option<&str>) -> Recipient {
+        Recipient {
+            email: SubscriberEmail::parse("ursula@example.com".into()).unwrap(),
+            name: "Ursula & co".into(),
+            locale: locale.map(String::from),
+            list_name: "Weekly".into(),
+        }
+    }
+
+    fn render_confirmation(templates: &EmailTemplates, locale: Option<&str>) -> (String, String) {
+        let email = templates
+            .render(
+                EmailTemplate::Confirmation,
+                &recipient(locale),
+                &[("confirmation_link", "https://example.com/confirm?a=1&b=2")],
+            )
+            .unwrap();
+        assert!(email.text_body.contains("https://example.com/confirm?a=1&b=2"));
+        assert!(email
+            .html_body
+            .contains(r#"href="https://example.com/confirm?a=1&amp;b=2""#));
+        (email.subject, email.text_body)
+    }
+
+    #[test]
+    fn the_embedded_templates_are_valid() {
+        assert_ok!(EmailTemplates::load(&settings(None)));
+    }
+
+    #[test]
+    fn emails_are_rendered_in_the_recipient_locale() {
+        let templates = EmailTemplates::load(&settings(None)).unwrap();
+        let (subject, text_body) = render_confirmation(&templates, Some("fr"));
+        assert_eq!(subject, "Confirmez votre inscription à Weekly");
+        assert!(text_body.starts_with("Bonjour Ursula & co,"));
+    }
+
+    #[test]
+    fn regional_locales_fall_back_to_their_language() {
+        let templates = EmailTemplates::load(&settings(None)).unwrap();
+        let (subject, _) = render_confirmation(&templates, Some("fr_CA"));
+        assert_eq!(subject, "Confirmez votre inscription à Weekly");
+    }
+
+    #[test]
+    fn unknown_locales_fall_back_to_the_default_one() {
+        let templates = EmailTemplates::load(&settings(None)).unwrap();
+        for locale in [None, Some("de")] {
+            let (subject, _) = render_confirmation(&templates, locale);
+            assert_eq!(subject, "Please confirm your subscription to Weekly");
+        }
+    }
+
+    #[test]
+    fn files_in_the_override_directory_take_precedence() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(directory.join("en")).unwrap();
+        std::fs::write(
+            directory.join("en/confirmation.subject.txt"),
+            "Confirm {{list_name}} now",
+        )
+        .unwrap();
+        let templates =
+            EmailTemplates::load(&settings(Some(directory.to_string_lossy().into()))).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let (subject, text_body) = render_confirmation(&templates, None);
+        assert_eq!(subject, "Confirm Weekly now");
+        assert!(text_body.starts_with("Hi Ursula & co,"));
+    }
+
+    #[test]
+    fn a_configured_subject_replaces_the_localized_ones() {
+        let mut templates = EmailTemplates::load(&settings(None)).unwrap();
+        templates
+            .set_subject(EmailTemplate::Confirmation, "{{list_name}}: one more step")
+            .unwrap();
+
+        for locale in [None, Some("fr")] {
+            let (subject, _) = render_confirmation(&templates, locale);
+            assert_eq!(subject, "Weekly: one more step");
+        }
+        assert_err!(templates.set_subject(EmailTemplate::Welcome, "{{confirmation_link}}"));
+    }
+
+    #[test]
+    fn invalid_overrides_are_rejected() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(directory.join("en")).unwrap();
+        std::fs::write(
+            directory.join("en/welcome.txt"),
+            "Confirm at {{confirmation_link}}",
+        )
+        .unwrap();
+        let templates = EmailTemplates::load(&settings(Some(directory.to_string_lossy().into())));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_err!(templates);
+    }
+
+    #[test]
+    fn a_default_locale_without_templates_is_rejected() {
+        let settings = EmailTemplateSettings {
+            override_directory: None,
+            default_locale: "de".into(),
+        };
+        assert_err!(EmailTemplates::load(&settings));
+    }
+}
@@ -0,0 +1,409 @@
+use crate::domain::{SubscriberEmail, SubscriberStatus, UnsubscribeToken};
+use crate::email_client::EmailClient;
+use crate::segments::SegmentDefinition;
+use crate::templating::{escape_html, IssueContext, Template, TemplateError};
+use secrecy::Secret;
+use sqlx::{Executor, PgPool, Postgres, QueryBuilder, Transaction};
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+/// Attempts, including the first one, before a delivery is recorded as
+/// failed.
+const MAX_ATTEMPTS: i16 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// How long a task waits before its next attempt, once it has been retried
+/// `n_retries` times: exponentially longer, starting at a minute. Shared by
+/// every queue so that they all back off alike.
+pub(crate) fn retry_delay_seconds(n_retries: i16) -> f64 {
+    60.0 * 2f64.powi(n_retries.into())
+}
+
+/// What happened to a single delivery task, as recorded in `issue_deliveries`.
+#[derive(Clone, Copy, Debug)]
+enum DeliveryOutcome {
+    Delivered,
+    Failed,
+    /// The recipient left the list, or their address is invalid.
+    Skipped,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Delivered => "delivered",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, &email_client, &base_url, &hmac_secret).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    let (transaction, task) = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    let (issue_id, email) = (task.newsletter_issue_id, task.subscriber_email);
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("subscriber_email", display(&email));
+
+    let issue = get_issue(pool, issue_id).await?;
+    let subscriber = get_confirmed_subscriber(pool, &email, issue.newsletter_id).await?;
+    // `Err` if the email could not be sent, which is worth another try.
+    let outcome = match (subscriber, SubscriberEmail::parse(email.clone())) {
+        (None, _) => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+            Ok(DeliveryOutcome::Skipped)
+        }
+        (Some(subscriber), Ok(subscriber_email)) => {
+            let unsubscribe_link =
+                unsubscribe_link(base_url, subscriber.id, issue.newsletter_id, hmac_secret);
+            let context = IssueContext {
+                name: &subscriber.name,
+                email: subscriber_email.as_ref(),
+                unsubscribe_url: &unsubscribe_link,
+            };
+            match issue.personalize(&context) {
+                Ok(personalized) => {
+                    deliver(email_client, &subscriber_email, &personalized, &unsubscribe_link)
+                        .await
+                        .map(|()| DeliveryOutcome::Delivered)
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.message = %e,
+                        "Failed to render the issue for a confirmed subscriber. \
+                         Recording the delivery as failed."
+                    );
+                    Ok(DeliveryOutcome::Failed)
+                }
+            }
+        }
+        (Some(_), Err(e)) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping a confirmed subscriber. \
+                 Their stored contact details are invalid."
+            );
+            Ok(DeliveryOutcome::Skipped)
+        }
+    };
+    match outcome {
+        Ok(outcome) => complete_task(transaction, issue_id, &email, outcome).await?,
+        Err(e) if task.n_retries + 1 >= MAX_ATTEMPTS => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Giving up."
+            );
+            complete_task(transaction, issue_id, &email, DeliveryOutcome::Failed).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Retrying later."
+            );
+            retry_later(transaction, issue_id, &email, task.n_retries).await?;
+        }
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// The signed link that lets the subscriber leave the list in one click.
+pub(crate) fn unsubscribe_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> String {
+    let token = UnsubscribeToken::for_subscriber(subscriber_id, list_id, hmac_secret);
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url,
+        token.as_ref()
+    )
+}
+
+/// Sends a personalized issue, with the headers that let mail clients
+/// offer a one-click unsubscribe.
+pub(crate) async fn deliver(
+    email_client: &EmailClient,
+    subscriber_email: &SubscriberEmail,
+    issue: &PersonalizedIssue,
+    unsubscribe_link: &str,
+) -> Result<(), anyhow::Error> {
+    let list_unsubscribe = format!("<{}>", unsubscribe_link);
+    email_client
+        .send_email_with_headers(
+            subscriber_email,
+            &issue.title,
+            &issue.html_content,
+            &issue.text_content,
+            &[
+                ("List-Unsubscribe", &list_unsubscribe),
+                ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+            ],
+        )
+        .await
+}
+
+/// Queues the issue for every confirmed member of its list, narrowed down
+/// to its segment if it has one.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let segment = sqlx::query!(
+        r#"
+        SELECT segment_definition FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?
+    .segment_definition
+    .map(SegmentDefinition::from_value)
+    .transpose()
+    .map_err(anyhow::Error::msg)?;
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) SELECT ",
+    );
+    query
+        .push_bind(newsletter_issue_id)
+        .push(
+            ", s.email FROM subscriptions s \
+            JOIN newsletter_subscriptions m ON m.subscriber_id = s.id \
+            WHERE m.newsletter_id = ",
+        )
+        .push_bind(list_id)
+        .push(" AND m.status = ")
+        .push_bind(SubscriberStatus::Confirmed.as_str());
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_sql(&mut query);
+    }
+    query.build().execute(&mut **transaction).await?;
+    Ok(())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+/// Records the outcome of the task and removes it from the queue.
+#[tracing::instrument(skip_all)]
+async fn complete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, outcome)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (newsletter_issue_id, subscriber_email)
+        DO UPDATE SET outcome = EXCLUDED.outcome, attempted_at = now()
+        "#,
+        issue_id,
+        email,
+        outcome.as_str()
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Leaves the task in the queue until its next attempt is due.
+#[tracing::instrument(skip_all)]
+async fn retry_later(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    n_retries: i16,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        retry_delay_seconds(n_retries)
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+}
+
+/// Subscribers may have unsubscribed since the issue was enqueued.
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    email: &str,
+    list_id: Uuid,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT s.id, s.name
+        FROM subscriptions s
+        JOIN newsletter_subscriptions m ON m.subscriber_id = s.id
+        WHERE s.email = $1 AND m.newsletter_id = $2 AND m.status = $3
+        "#,
+        email,
+        list_id,
+        SubscriberStatus::Confirmed.as_str()
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber)
+}
+
+pub(crate) struct NewsletterIssue {
+    pub(crate) newsletter_id: Uuid,
+    pub(crate) title: String,
+    pub(crate) text_content: String,
+    pub(crate) html_content: String,
+}
+
+pub(crate) struct PersonalizedIssue {
+    pub(crate) title: String,
+    pub(crate) text_content: String,
+    pub(crate) html_content: String,
+}
+
+impl NewsletterIssue {
+    /// Renders the issue for a single recipient. An unsubscribe link is
+    /// appended to bodies that do not place one themselves.
+    pub(crate) fn personalize(&self, context: &IssueContext) -> Result<PersonalizedIssue, TemplateError> {
+        let title = Template::parse(&self.title, IssueContext::VARIABLES)?;
+        let html = Template::parse(&self.html_content, IssueContext::VARIABLES)?;
+        let text = Template::parse(&self.text_content, IssueContext::VARIABLES)?;
+        let mut html_content = html.render_html(context);
+        if !html.uses("unsubscribe_url") {
+            html_content.push_str(&format!(
+                "<p><a href=\"{}\">Unsubscribe</a></p>",
+                escape_html(context.unsubscribe_url)
+            ));
+        }
+        let mut text_content = text.render_text(context);
+        if !text.uses("unsubscribe_url") {
+            text_content.push_str(&format!("\n\nUnsubscribe: {}", context.unsubscribe_url));
+        }
+        Ok(PersonalizedIssue {
+            title: title.render_text(context),
+            text_content,
+            html_content,
+        })
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT newsletter_id, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
@@ -0,0 +1,66 @@
+use crate::domain::IssueStatus;
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, ExecutionOutcome};
+use sqlx::{Executor, PgPool};
+use std::time::Duration;
+use tracing::{field::display, Span};
+
+/// Enqueues scheduled issues for delivery once their `send_at` has passed.
+///
+/// The schedule lives in the database, so issues that came due while the
+/// application was down are picked up as soon as it starts again.
+pub async fn run_scheduler_until_stopped(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        match try_publish_due_issue(&pool).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_publish_due_issue(pool: &PgPool) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // Locking the row keeps concurrent schedulers, and cancellations, out.
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, newsletter_id
+        FROM newsletter_issues
+        WHERE status = $1 AND send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        IssueStatus::Scheduled as _,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("newsletter_issue_id", display(issue.newsletter_issue_id));
+
+    enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id, issue.newsletter_id)
+        .await?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue.newsletter_issue_id,
+        IssueStatus::Published as _,
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
@@ -1,9 +1,23 @@
+pub mod authentication;
 pub mod configuration;
 pub mod routes;
+pub mod session_state;
 pub mod startup;
 pub mod telemetry;
 mod domain;
 pub mod email_client;
+pub mod email_queue;
+pub mod email_templates;
+pub mod idempotency;
+pub mod utils;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod lists;
+pub mod personal_data;
+pub mod segments;
+pub mod subscriber_export;
+pub mod subscriber_import;
+pub mod templating;
 
 #[cfg(test)]
 mod tests {
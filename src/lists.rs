@@ -0,0 +1,134 @@
+use crate::domain::{InvalidStatusTransition, SubscriberStatus};
+use crate::routes::error_chain_fmt;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Returns the id of the list a request refers to, or of the default list
+/// if it did not name one.
+/// `None` if there is no such list.
+#[tracing::instrument(name = "Resolve newsletter list", skip(executor))]
+pub async fn resolve_list_id(
+    executor: impl PgExecutor<'_>,
+    list_id: Option<Uuid>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT newsletter_id
+        FROM newsletters
+        WHERE newsletter_id = $1 OR ($1 IS NULL AND is_default)
+        "#,
+        list_id as Option<Uuid>
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.newsletter_id))
+}
+
+#[derive(thiserror::Error)]
+pub enum MembershipError {
+    #[error("The subscriber is not a member of the list.")]
+    NotAMember,
+    #[error(transparent)]
+    InvalidTransition(#[from] InvalidStatusTransition),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for MembershipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Locks the membership, so that its status cannot change until the
+/// transaction ends.
+#[tracing::instrument(name = "Get membership status", skip(transaction))]
+pub async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberStatus>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT status FROM newsletter_subscriptions
+        WHERE newsletter_id = $1 AND subscriber_id = $2
+        FOR UPDATE
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    row.map(|r| SubscriberStatus::parse(&r.status).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// New memberships always start out pending.
+#[tracing::instrument(name = "Insert pending membership", skip(transaction))]
+pub async fn insert_pending_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_subscriptions (newsletter_id, subscriber_id, status)
+        VALUES ($1, $2, $3)
+        "#,
+        list_id,
+        subscriber_id,
+        SubscriberStatus::PendingConfirmation.as_str()
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Moves a membership from `from`, as read by `get_membership_status`,
+/// to `to`.
+#[tracing::instrument(name = "Set membership status", skip(transaction))]
+pub async fn set_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    from: SubscriberStatus,
+    to: SubscriberStatus,
+) -> Result<(), MembershipError> {
+    from.transition_to(to)?;
+    let confirmed_at = (to == SubscriberStatus::Confirmed).then(Utc::now);
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_subscriptions
+        SET status = $4, confirmed_at = COALESCE($5, confirmed_at)
+        WHERE newsletter_id = $1 AND subscriber_id = $2 AND status = $3
+        "#,
+        list_id,
+        subscriber_id,
+        from.as_str(),
+        to.as_str(),
+        confirmed_at
+    );
+    let result = transaction
+        .execute(query)
+        .await
+        .context("Failed to update the membership status.")?;
+    if result.rows_affected() == 0 {
+        return Err(MembershipError::NotAMember);
+    }
+    Ok(())
+}
+
+/// Returns the status the membership had before.
+pub async fn transition_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    to: SubscriberStatus,
+) -> Result<SubscriberStatus, MembershipError> {
+    let from = get_membership_status(transaction, list_id, subscriber_id)
+        .await?
+        .ok_or(MembershipError::NotAMember)?;
+    set_membership_status(transaction, list_id, subscriber_id, from, to).await?;
+    Ok(from)
+}
@@ -1,4 +1,3 @@
-use actix_web::{HttpRequest, Responder};
 use zero2prod::configuration::get_configuration;
 use zero2prod::startup::{Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
@@ -0,0 +1,322 @@
+//! What we hold about a subscriber, and how it goes away on request.
+//!
+//! Issue deliveries are kept once anonymized, as they make up the history
+//! of every issue; everything else that mentions the subscriber is deleted.
+//! Rows are matched on the address exactly as stored: addresses are unique
+//! only as written, and two that differ in case may belong to two people.
+//! Email events are the exception. Providers do not always report addresses
+//! in the case they were typed in, so the webhook attributes an event to
+//! every address that matches it ignoring case, and so do we here.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct PersonalData {
+    pub subscriber: SubscriberRecord,
+    pub memberships: Vec<MembershipRecord>,
+    pub tags: Vec<String>,
+    pub subscription_tokens: Vec<TokenRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    pub email_events: Vec<EmailEventRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub locale: Option<String>,
+    pub address_confirmed: bool,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MembershipRecord {
+    pub list_id: Uuid,
+    pub list_name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TokenRecord {
+    pub subscription_token: String,
+    pub list_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub outcome: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct EmailEventRecord {
+    pub event_type: String,
+    pub bounce_type: Option<String>,
+    pub message_id: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Who asked for an erasure, recorded in its audit row.
+#[derive(Debug, Clone, Copy)]
+pub enum ErasureRequester {
+    Subscriber,
+    Admin(Uuid),
+}
+
+impl ErasureRequester {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErasureRequester::Subscriber => "subscriber",
+            ErasureRequester::Admin(_) => "admin",
+        }
+    }
+
+    fn admin_id(&self) -> Option<Uuid> {
+        match self {
+            ErasureRequester::Subscriber => None,
+            ErasureRequester::Admin(user_id) => Some(*user_id),
+        }
+    }
+}
+
+/// Personal data links are stored hashed, so that the table cannot be used
+/// to read anybody's data.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// `None` if there is no such subscriber.
+#[tracing::instrument(name = "Collect personal data", skip(pool))]
+pub async fn collect_personal_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<PersonalData>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, name, locale, address_confirmed, subscribed_at
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(None);
+    };
+    let memberships = sqlx::query_as!(
+        MembershipRecord,
+        r#"
+        SELECT m.newsletter_id AS list_id, n.name AS list_name, m.status,
+            m.subscribed_at, m.confirmed_at
+        FROM newsletter_subscriptions m
+        JOIN newsletters n ON n.newsletter_id = m.newsletter_id
+        WHERE m.subscriber_id = $1
+        ORDER BY m.subscribed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the memberships.")?;
+    let tags = sqlx::query!(
+        r#"SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the tags.")?
+    .into_iter()
+    .map(|r| r.tag)
+    .collect();
+    let subscription_tokens = sqlx::query_as!(
+        TokenRecord,
+        r#"
+        SELECT subscription_token, newsletter_id AS list_id, created_at, consumed_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscription tokens.")?;
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.outcome, d.attempted_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1
+        ORDER BY d.attempted_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the delivery history.")?;
+    let email_events = sqlx::query_as!(
+        EmailEventRecord,
+        r#"
+        SELECT event_type, bounce_type, message_id, occurred_at
+        FROM email_events
+        WHERE lower(email) = lower($1)
+        ORDER BY occurred_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the email events.")?;
+    Ok(Some(PersonalData {
+        subscriber,
+        memberships,
+        tags,
+        subscription_tokens,
+        deliveries,
+        email_events,
+    }))
+}
+
+/// Deletes the subscriber and every row that refers to them, except for
+/// their deliveries, whose address is replaced with a placeholder.
+/// Returns the id of the audit record, or `None` if there was no such
+/// subscriber.
+#[tracing::instrument(name = "Erase a subscriber", skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    requester: ErasureRequester,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let email = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let Some(email) = email.map(|r| r.email) else {
+        return Ok(None);
+    };
+    let erasure_id = Uuid::new_v4();
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+            email
+        ))
+        .await
+        .context("Failed to delete the queued deliveries.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM personal_data_email_queue WHERE email = $1"#,
+            email
+        ))
+        .await
+        .context("Failed to delete the queued personal data emails.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM confirmation_email_queue WHERE subscriber_id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the queued confirmation emails.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM welcome_email_queue WHERE subscriber_id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the queued welcome emails.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM unsubscribe_email_queue WHERE subscriber_id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the queued unsubscribe emails.")?;
+    // Tokens refer to the subscriber, so they must go first.
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the subscription tokens.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM personal_data_tokens WHERE subscriber_id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the personal data tokens.")?;
+    let memberships = transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM newsletter_subscriptions WHERE subscriber_id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the memberships.")?
+        .rows_affected();
+    let deleted_email_events = transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM email_events WHERE lower(email) = lower($1)"#,
+            email
+        ))
+        .await
+        .context("Failed to delete the email events.")?
+        .rows_affected();
+    // The placeholder is unique per erasure, as deliveries are keyed by
+    // issue and address.
+    let anonymized_deliveries = transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE issue_deliveries SET subscriber_email = 'erased-' || $2
+            WHERE subscriber_email = $1
+            "#,
+            email,
+            erasure_id.to_string(),
+        ))
+        .await
+        .context("Failed to anonymize the delivery history.")?
+        .rows_affected();
+    // Tags go away with the subscriber.
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM subscriptions WHERE id = $1"#,
+            subscriber_id
+        ))
+        .await
+        .context("Failed to delete the subscriber.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO subscriber_erasures (
+                erasure_id, requested_by, admin_id, memberships,
+                anonymized_deliveries, deleted_email_events
+            )
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            erasure_id,
+            requester.as_str(),
+            requester.admin_id(),
+            memberships as i32,
+            anonymized_deliveries as i32,
+            deleted_email_events as i32,
+        ))
+        .await
+        .context("Failed to record the erasure.")?;
+    Ok(Some(erasure_id))
+}
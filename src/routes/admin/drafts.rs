@@ -0,0 +1,497 @@
+use crate::authentication::UserId;
+use crate::domain::{IssueContent, IssueStatus, IssueTitle, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::{
+    deliver, enqueue_delivery_tasks, unsubscribe_link, NewsletterIssue, PersonalizedIssue,
+};
+use crate::session_state::TypedSession;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::templating::{IssueContext, TemplateError};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    /// Generated from `html_content` if left empty.
+    text_content: String,
+    html_content: String,
+    /// The list to publish to, the default one if missing.
+    list_id: Option<Uuid>,
+}
+
+impl DraftFormData {
+    fn parse(self) -> Result<(IssueTitle, IssueContent, Option<Uuid>), String> {
+        let title = IssueTitle::parse(self.title)?;
+        let content = IssueContent::parse(self.html_content, Some(self.text_content))?;
+        Ok((title, content, self.list_id))
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendFormData {
+    email: String,
+}
+
+struct Draft {
+    title: String,
+    text_content: String,
+    html_content: String,
+    newsletter_id: Uuid,
+    status: IssueStatus,
+}
+
+impl Draft {
+    /// Renders the draft the way delivery would for a subscriber called
+    /// "Test Subscriber" at `email`. The unsubscribe link is signed for a
+    /// subscriber that does not exist, so it can be followed but
+    /// unsubscribes nobody. Returns the link along with the issue.
+    fn personalize_sample(
+        &self,
+        email: &str,
+        base_url: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Result<(PersonalizedIssue, String), TemplateError> {
+        let unsubscribe_link =
+            unsubscribe_link(base_url, Uuid::nil(), self.newsletter_id, hmac_secret);
+        let context = IssueContext {
+            name: "Test Subscriber",
+            email,
+            unsubscribe_url: &unsubscribe_link,
+        };
+        let issue = NewsletterIssue {
+            newsletter_id: self.newsletter_id,
+            title: self.title.clone(),
+            text_content: self.text_content.clone(),
+            html_content: self.html_content.clone(),
+        };
+        Ok((issue.personalize(&context)?, unsubscribe_link))
+    }
+}
+
+pub async fn new_draft_form(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    Ok(draft_form(
+        &session,
+        "New draft",
+        "/admin/issues",
+        None,
+        &lists,
+    ))
+}
+
+pub async fn edit_draft_form(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let draft = match get_draft(&pool, issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if draft.status != IssueStatus::Draft {
+        session
+            .insert_flash("Only drafts can be edited.")
+            .map_err(e500)?;
+        return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+    }
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    Ok(draft_form(
+        &session,
+        "Edit draft",
+        &format!("/admin/issues/{}", issue_id),
+        Some(&draft),
+        &lists,
+    ))
+}
+
+#[tracing::instrument(name = "Create a draft issue", skip(form, pool, session))]
+pub async fn create_draft(
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let (title, content, list_id) = match form.0.parse() {
+        Ok(draft) => draft,
+        Err(e) => {
+            session.insert_flash(&e).map_err(e500)?;
+            return Ok(see_other("/admin/issues/new"));
+        }
+    };
+    let list_id = match resolve_list(&pool, list_id, &session).await? {
+        Some(list_id) => list_id,
+        None => return Ok(see_other("/admin/issues/new")),
+    };
+    let issue_id = insert_draft(&pool, list_id, *user_id, &title, &content)
+        .await
+        .map_err(e500)?;
+    session.insert_flash("The draft has been saved.").map_err(e500)?;
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+#[tracing::instrument(name = "Update a draft issue", skip(form, pool, session))]
+pub async fn update_draft(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let edit_url = format!("/admin/issues/{}/edit", issue_id);
+    let (title, content, list_id) = match form.0.parse() {
+        Ok(draft) => draft,
+        Err(e) => {
+            session.insert_flash(&e).map_err(e500)?;
+            return Ok(see_other(&edit_url));
+        }
+    };
+    let list_id = match resolve_list(&pool, list_id, &session).await? {
+        Some(list_id) => list_id,
+        None => return Ok(see_other(&edit_url)),
+    };
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, newsletter_id = $5
+        WHERE newsletter_issue_id = $1 AND status = $6
+        "#,
+        issue_id,
+        title.as_ref(),
+        content.text(),
+        content.html(),
+        list_id,
+        IssueStatus::Draft as _,
+    )
+    .execute(&**pool)
+    .await
+    .context("Failed to update the draft.")
+    .map_err(e500)?
+    .rows_affected()
+        == 1;
+    let message = if updated {
+        "The draft has been saved."
+    } else {
+        "Only drafts can be edited."
+    };
+    session.insert_flash(message).map_err(e500)?;
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+/// Renders both bodies of an issue the way subscribers will receive them,
+/// with sample subscriber details.
+pub async fn preview_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let draft = match get_draft(&pool, issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (issue, _) = draft
+        .personalize_sample("subscriber@example.com", &base_url.0, &hmac_secret.0)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview: {title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <h2>HTML</h2>
+    <hr>
+    {html}
+    <hr>
+    <h2>Plain text</h2>
+    <pre>{text}</pre>
+    <p><a href="/admin/issues/{issue_id}">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+            html = issue.html_content,
+            text = htmlescape::encode_minimal(&issue.text_content),
+        )))
+}
+
+/// Sends the issue to a single address, e.g. the author's own inbox,
+/// without touching the delivery queue. It goes out the way deliveries
+/// do, with placeholders filled in with sample values.
+#[tracing::instrument(
+    name = "Send a test email",
+    skip(form, pool, email_client, base_url, hmac_secret, session)
+)]
+pub async fn send_test_email(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<TestSendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let draft = match get_draft(&pool, issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let message = match SubscriberEmail::parse(form.0.email) {
+        Ok(recipient) => {
+            match draft.personalize_sample(recipient.as_ref(), &base_url.0, &hmac_secret.0) {
+                Ok((mut issue, unsubscribe_link)) => {
+                    issue.title = format!("[Test] {}", issue.title);
+                    match deliver(&email_client, &recipient, &issue, &unsubscribe_link).await {
+                        Ok(()) => format!("A test email has been sent to {}.", recipient),
+                        Err(e) => {
+                            tracing::error!(
+                                error.cause_chain = ?e,
+                                error.message = %e,
+                                "Failed to send a test email."
+                            );
+                            format!("Failed to send the test email to {}.", recipient)
+                        }
+                    }
+                }
+                Err(e) => e.to_string(),
+            }
+        }
+        Err(e) => e,
+    };
+    session.insert_flash(&message).map_err(e500)?;
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+/// Hands a draft over to the delivery queue.
+#[tracing::instrument(name = "Publish a draft issue", skip(pool, session))]
+pub async fn publish_draft(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let published = try_publish_draft(&pool, issue_id).await.map_err(e500)?;
+    let message = if published {
+        "The issue has been published."
+    } else {
+        "Only drafts can be published."
+    };
+    session.insert_flash(message).map_err(e500)?;
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+async fn try_publish_draft(pool: &PgPool, issue_id: Uuid) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // The status check makes a double submission publish the draft only once.
+    let issue = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, published_at = now()
+        WHERE newsletter_issue_id = $1 AND status = $3
+        RETURNING newsletter_id
+        "#,
+        issue_id,
+        IssueStatus::Published as _,
+        IssueStatus::Draft as _,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to publish the draft.")?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(false),
+    };
+    enqueue_delivery_tasks(&mut transaction, issue_id, issue.newsletter_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a draft.")?;
+    Ok(true)
+}
+
+/// Falls back to the default list, flashing an error for unknown ones.
+async fn resolve_list(
+    pool: &PgPool,
+    list_id: Option<Uuid>,
+    session: &TypedSession,
+) -> Result<Option<Uuid>, actix_web::Error> {
+    let list_id = crate::lists::resolve_list_id(pool, list_id)
+        .await
+        .context("Failed to look up the newsletter list.")
+        .map_err(e500)?;
+    if list_id.is_none() {
+        session
+            .insert_flash("Unknown newsletter list.")
+            .map_err(e500)?;
+    }
+    Ok(list_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_draft(
+    pool: &PgPool,
+    list_id: Uuid,
+    author_id: Uuid,
+    title: &IssueTitle,
+    content: &IssueContent,
+) -> Result<Uuid, anyhow::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            newsletter_id,
+            author_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            status
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now(), $7)
+        "#,
+        newsletter_issue_id,
+        list_id,
+        author_id,
+        title.as_ref(),
+        content.text(),
+        content.html(),
+        IssueStatus::Draft as _,
+    );
+    pool.execute(query)
+        .await
+        .context("Failed to store the draft.")?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Get issue content", skip(pool))]
+async fn get_draft(pool: &PgPool, issue_id: Uuid) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT
+            title,
+            text_content,
+            html_content,
+            newsletter_id,
+            status AS "status: IssueStatus"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the issue.")?;
+    Ok(draft)
+}
+
+async fn get_lists(pool: &PgPool) -> Result<Vec<(Uuid, String)>, anyhow::Error> {
+    let lists = sqlx::query!(
+        r#"SELECT newsletter_id, name FROM newsletters ORDER BY is_default DESC, name"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the newsletter lists.")?
+    .into_iter()
+    .map(|r| (r.newsletter_id, r.name))
+    .collect();
+    Ok(lists)
+}
+
+fn draft_form(
+    session: &TypedSession,
+    heading: &str,
+    action: &str,
+    draft: Option<&Draft>,
+    lists: &[(Uuid, String)],
+) -> HttpResponse {
+    let msg_html = match session.take_flash() {
+        Some(message) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(&message)
+        ),
+        None => String::new(),
+    };
+    let options: String = lists
+        .iter()
+        .map(|(list_id, name)| {
+            let selected = if draft.map(|d| d.newsletter_id) == Some(*list_id) {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{}>{}</option>"#,
+                list_id,
+                selected,
+                htmlescape::encode_minimal(name)
+            )
+        })
+        .collect();
+    let (title, text_content, html_content) = match draft {
+        Some(draft) => (
+            draft.title.as_str(),
+            draft.text_content.as_str(),
+            draft.html_content.as_str(),
+        ),
+        None => ("", "", ""),
+    };
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{heading}</title>
+</head>
+<body>
+    {msg_html}
+    <form action="{action}" method="post">
+        <label>Title
+            <input type="text" name="title" value="{title}">
+        </label>
+        <br>
+        <label>List
+            <select name="list_id">{options}</select>
+        </label>
+        <br>
+        <label>Plain text content
+            <textarea name="text_content" rows="20" cols="80">{text_content}</textarea>
+        </label>
+        <br>
+        <label>HTML content
+            <textarea name="html_content" rows="20" cols="80">{html_content}</textarea>
+        </label>
+        <br>
+        <button type="submit">Save draft</button>
+    </form>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_attribute(title),
+            text_content = htmlescape::encode_minimal(text_content),
+            html_content = htmlescape::encode_minimal(html_content),
+        ))
+}
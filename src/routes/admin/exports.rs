@@ -0,0 +1,136 @@
+use super::subscribers::SubscriberApiError;
+use crate::domain::SubscriberStatus;
+use crate::lists::resolve_list_id;
+use crate::subscriber_export::{ExportFormat, ExportRow};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Rows are sent to the client in chunks of about this many bytes.
+const CHUNK_SIZE: usize = 16 * 1024;
+/// Chunks waiting for a slow client, past which reading rows pauses.
+const BUFFERED_CHUNKS: usize = 4;
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    /// The list to export, the default one if missing.
+    list_id: Option<Uuid>,
+    #[serde(default)]
+    format: ExportFormat,
+    /// Comma separated statuses to export, all of them if missing.
+    status: Option<String>,
+}
+
+/// Streams the members of a list as CSV or NDJSON, oldest first.
+/// Rows are read from the database as the client downloads them, so
+/// memory use does not grow with the size of the list.
+#[tracing::instrument(name = "Export subscribers", skip(query, pool))]
+pub async fn export_subscribers(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberApiError> {
+    let query = query.into_inner();
+    let statuses = query
+        .status
+        .as_deref()
+        .map(|statuses| {
+            statuses
+                .split(',')
+                .map(|s| SubscriberStatus::parse(s.trim()).map(|s| s.as_str().to_string()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+    let list_id = resolve_list_id(&**pool, query.list_id)
+        .await
+        .context("Failed to look up the newsletter list.")?
+        .ok_or_else(|| SubscriberApiError::ValidationError("Unknown newsletter list.".into()))?;
+
+    let format = query.format;
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::spawn(
+        write_export(pool.get_ref().clone(), list_id, statuses, format, sender)
+            .instrument(tracing::Span::current()),
+    );
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"subscribers.{}\"",
+                format.extension()
+            ),
+        ))
+        .streaming(body))
+}
+
+/// A failure half way through cuts the download short, so that a partial
+/// export is not mistaken for a complete one.
+async fn write_export(
+    pool: PgPool,
+    list_id: Uuid,
+    statuses: Option<Vec<String>>,
+    format: ExportFormat,
+    sender: mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) {
+    if let Err(e) = try_write_export(&pool, list_id, statuses, format, &sender).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to export subscribers."
+        );
+        let _ = sender.send(Err(e)).await;
+    }
+}
+
+async fn try_write_export(
+    pool: &PgPool,
+    list_id: Uuid,
+    statuses: Option<Vec<String>>,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut rows = sqlx::query_as!(
+        ExportRow,
+        r#"
+        SELECT s.id, s.email, s.name, m.status, m.subscribed_at, m.confirmed_at
+        FROM newsletter_subscriptions m
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        WHERE m.newsletter_id = $1
+            AND ($2::text[] IS NULL OR m.status = ANY($2))
+        ORDER BY m.subscribed_at, s.id
+        "#,
+        list_id,
+        statuses.as_deref(),
+    )
+    .fetch(pool);
+
+    let mut chunk = format.header().as_bytes().to_vec();
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("Failed to read the subscribers to export.")?
+    {
+        format.encode(&row, &mut chunk);
+        if chunk.len() >= CHUNK_SIZE {
+            let full = std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE));
+            if sender.send(Ok(full.into())).await.is_err() {
+                tracing::info!("The client stopped downloading the export.");
+                return Ok(());
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        let _ = sender.send(Ok(chunk.into())).await;
+    }
+    Ok(())
+}
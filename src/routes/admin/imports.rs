@@ -0,0 +1,269 @@
+use super::subscribers::SubscriberApiError;
+use crate::authentication::UserId;
+use crate::domain::{NewSubscriber, SubscriberStatus};
+use crate::lists::resolve_list_id;
+use crate::routes::mark_address_confirmed;
+use crate::subscriber_import::{ImportFormat, RowDecoder, RowError};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use futures_util::StreamExt;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Rows written per transaction.
+const BATCH_SIZE: usize = 500;
+/// Failed rows past this many are counted but not listed.
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+#[derive(serde::Deserialize)]
+pub struct ImportQuery {
+    /// The list to import into, the default one if missing.
+    list_id: Option<Uuid>,
+    /// Imports memberships as confirmed instead of pending confirmation.
+    #[serde(default)]
+    pre_confirmed: bool,
+    /// Where the consent of pre-confirmed subscribers was collected,
+    /// e.g. `signup form on the old website`.
+    consent_source: Option<String>,
+}
+
+#[derive(serde::Serialize, Default)]
+struct ImportReport {
+    import_id: Uuid,
+    imported: i32,
+    /// Rows for addresses that are already on the list, whatever their
+    /// status there.
+    duplicates: i32,
+    failed: i32,
+    errors: Vec<RowError>,
+}
+
+impl ImportReport {
+    fn record_error(&mut self, error: RowError) {
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error);
+        }
+    }
+}
+
+struct Import {
+    import_id: Uuid,
+    list_id: Uuid,
+    pre_confirmed: bool,
+}
+
+/// Imports a CSV or JSON lines upload into a list, without sending any
+/// email. Pending subscribers can ask for a confirmation link through
+/// `/subscriptions/resend-confirmation`.
+///
+/// The upload is read as it arrives and written in batches, each in its
+/// own transaction: batches written before a failure stay imported.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(request, payload, query, pool, user_id),
+    fields(import_id = tracing::field::Empty)
+)]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    mut payload: web::Payload,
+    query: web::Query<ImportQuery>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, SubscriberApiError> {
+    let format = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(ImportFormat::from_content_type)
+        .ok_or_else(|| {
+            SubscriberApiError::ValidationError(
+                "Uploads must be `text/csv` or `application/x-ndjson`.".into(),
+            )
+        })?;
+    let query = query.into_inner();
+    let consent_source = query
+        .consent_source
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    if query.pre_confirmed && consent_source.is_none() {
+        return Err(SubscriberApiError::ValidationError(
+            "Pre-confirmed imports must have a `consent_source`.".into(),
+        ));
+    }
+    if consent_source.is_some_and(|s| s.chars().count() > 200) {
+        return Err(SubscriberApiError::ValidationError(
+            "`consent_source` cannot be longer than 200 characters.".into(),
+        ));
+    }
+    let list_id = resolve_list_id(&**pool, query.list_id)
+        .await
+        .context("Failed to look up the newsletter list.")?
+        .ok_or_else(|| SubscriberApiError::ValidationError("Unknown newsletter list.".into()))?;
+
+    let import = Import {
+        import_id: Uuid::new_v4(),
+        list_id,
+        pre_confirmed: query.pre_confirmed,
+    };
+    tracing::Span::current().record("import_id", tracing::field::display(import.import_id));
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id, newsletter_id, imported_by, pre_confirmed, consent_source
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        import.import_id,
+        import.list_id,
+        *user_id.into_inner(),
+        import.pre_confirmed,
+        consent_source,
+    )
+    .execute(&**pool)
+    .await
+    .context("Failed to record the import.")?;
+
+    let mut report = ImportReport {
+        import_id: import.import_id,
+        ..Default::default()
+    };
+    let mut decoder = RowDecoder::new(format);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut finished = false;
+    while !finished {
+        let rows = match payload.next().await {
+            Some(chunk) => decoder.feed(&chunk.context("Failed to read the upload.")?),
+            None => {
+                finished = true;
+                decoder.finish()
+            }
+        }
+        .map_err(SubscriberApiError::ValidationError)?;
+        for row in rows {
+            match row {
+                Ok(subscriber) => batch.push(subscriber),
+                Err(e) => report.record_error(e),
+            }
+            if batch.len() == BATCH_SIZE {
+                import_batch(&pool, &import, &mut batch, &mut report).await?;
+            }
+        }
+    }
+    import_batch(&pool, &import, &mut batch, &mut report).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Writes `batch`, emptying it, and the counts so far.
+async fn import_batch(
+    pool: &PgPool,
+    import: &Import,
+    batch: &mut Vec<NewSubscriber>,
+    report: &mut ImportReport,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    for subscriber in batch.drain(..) {
+        if import_row(&mut transaction, import, &subscriber).await? {
+            report.imported += 1;
+        } else {
+            report.duplicates += 1;
+        }
+    }
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET imported = $2, duplicates = $3, failed = $4
+        WHERE import_id = $1
+        "#,
+        import.import_id,
+        report.imported,
+        report.duplicates,
+        report.failed,
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to update the import counts.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")?;
+    Ok(())
+}
+
+/// Returns `false` if the address is already on the list. Existing
+/// memberships are left as they are, so that nobody is resubscribed.
+async fn import_row(
+    transaction: &mut Transaction<'_, Postgres>,
+    import: &Import,
+    subscriber: &NewSubscriber,
+) -> Result<bool, anyhow::Error> {
+    let status = if import.pre_confirmed {
+        SubscriberStatus::Confirmed
+    } else {
+        SubscriberStatus::PendingConfirmation
+    };
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, address_confirmed, locale)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        Utc::now(),
+        import.pre_confirmed,
+        subscriber.locale.as_ref().map(|locale| locale.as_ref()),
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to insert the subscriber.")?;
+    let subscriber_id = match inserted {
+        Some(row) => row.id,
+        None => {
+            sqlx::query!(
+                r#"SELECT id FROM subscriptions WHERE email = $1"#,
+                subscriber.email.as_ref()
+            )
+            .fetch_one(&mut **transaction)
+            .await
+            .context("Failed to look up the existing subscriber.")?
+            .id
+        }
+    };
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_subscriptions (
+            newsletter_id, subscriber_id, status, import_id, confirmed_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        "#,
+        import.list_id,
+        subscriber_id,
+        status.as_str(),
+        import.import_id,
+        import.pre_confirmed.then(Utc::now),
+    );
+    let is_new_member = transaction
+        .execute(query)
+        .await
+        .context("Failed to insert the membership.")?
+        .rows_affected()
+        == 1;
+    if is_new_member && import.pre_confirmed {
+        // As for a confirmation link, the address counts as confirmed too.
+        mark_address_confirmed(transaction, subscriber_id)
+            .await
+            .context("Failed to mark the address as confirmed.")?;
+    }
+    Ok(is_new_member)
+}
@@ -0,0 +1,273 @@
+use crate::domain::IssueStatus;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    list_name: String,
+    author: Option<String>,
+    published_at: DateTime<Utc>,
+    status: IssueStatus,
+    send_at: Option<DateTime<Utc>>,
+    pending: i64,
+    delivered: i64,
+    failed: i64,
+    skipped: i64,
+}
+
+impl IssueSummary {
+    fn stats_html(&self) -> String {
+        match (self.status, self.send_at) {
+            (IssueStatus::Scheduled, Some(send_at)) => format!(
+                r#"Scheduled for {}
+            <form action="/admin/issues/{}/cancel" method="post">
+                <button type="submit">Cancel</button>
+            </form>"#,
+                send_at.format("%Y-%m-%d %H:%M UTC"),
+                self.newsletter_issue_id
+            ),
+            (IssueStatus::Cancelled, _) => "Cancelled".into(),
+            (IssueStatus::Draft, _) => "Draft".into(),
+            _ => format!(
+                "{} delivered, {} failed, {} skipped, {} pending",
+                self.delivered, self.failed, self.skipped, self.pending
+            ),
+        }
+    }
+
+    fn published_html(&self) -> String {
+        match self.status {
+            IssueStatus::Published => self.published_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            _ => "-".into(),
+        }
+    }
+}
+
+pub async fn list_issues(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let flash_html = match session.take_flash() {
+        Some(message) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(&message)
+        ),
+        None => String::new(),
+    };
+    let issues = get_issue_summaries(&pool, None).await.map_err(e500)?;
+    let mut rows = String::new();
+    for issue in &issues {
+        rows.push_str(&format!(
+            r#"<tr>
+            <td><a href="/admin/issues/{}">{}</a></td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>
+        "#,
+            issue.newsletter_issue_id,
+            htmlescape::encode_minimal(&issue.title),
+            htmlescape::encode_minimal(&issue.list_name),
+            issue.published_html(),
+            htmlescape::encode_minimal(issue.author.as_deref().unwrap_or("unknown")),
+            issue.stats_html(),
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter issues</title>
+</head>
+<body>
+    {flash_html}
+    <p><a href="/admin/issues/new">New draft</a></p>
+    <table>
+        <tr>
+            <th>Title</th>
+            <th>List</th>
+            <th>Published</th>
+            <th>Author</th>
+            <th>Status</th>
+        </tr>
+        {rows}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn issue_details(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let flash_html = match session.take_flash() {
+        Some(message) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(&message)
+        ),
+        None => String::new(),
+    };
+    let issue = match get_issue_summaries(&pool, Some(issue_id))
+        .await
+        .map_err(e500)?
+        .pop()
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let content = sqlx::query!(
+        r#"SELECT html_content FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&**pool)
+    .await
+    .context("Failed to retrieve the issue content.")
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {flash_html}
+    <h1>{title}</h1>
+    <p>{list}, by {author}.</p>
+    <p>{published}{stats}</p>
+    {actions}
+    <hr>
+    {content}
+    <hr>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+            list = htmlescape::encode_minimal(&issue.list_name),
+            published = if issue.status == IssueStatus::Published {
+                format!("Published on {}: ", issue.published_at.format("%Y-%m-%d %H:%M UTC"))
+            } else {
+                String::new()
+            },
+            author = htmlescape::encode_minimal(issue.author.as_deref().unwrap_or("unknown")),
+            stats = issue.stats_html(),
+            actions = actions_html(&issue),
+            content = content.html_content,
+        )))
+}
+
+fn actions_html(issue: &IssueSummary) -> String {
+    let issue_id = issue.newsletter_issue_id;
+    let preview = format!(
+        r#"<p><a href="/admin/issues/{issue_id}/preview">Preview</a></p>
+    <form action="/admin/issues/{issue_id}/test" method="post">
+        <label>Send a test to
+            <input type="email" name="email" placeholder="Enter an email address">
+        </label>
+        <button type="submit">Send test</button>
+    </form>"#
+    );
+    if issue.status != IssueStatus::Draft {
+        return preview;
+    }
+    format!(
+        r#"<p><a href="/admin/issues/{issue_id}/edit">Edit</a></p>
+    {preview}
+    <form action="/admin/issues/{issue_id}/publish" method="post">
+        <button type="submit">Publish</button>
+    </form>"#
+    )
+}
+
+/// Stops a scheduled issue from being sent. Issues already handed over
+/// to the delivery queue cannot be cancelled.
+pub async fn cancel_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let cancelled = cancel_scheduled_issue(&pool, issue_id.into_inner())
+        .await
+        .map_err(e500)?;
+    let message = if cancelled {
+        "The issue has been cancelled."
+    } else {
+        "The issue is not scheduled anymore and cannot be cancelled."
+    };
+    session.insert_flash(message).map_err(e500)?;
+    Ok(see_other("/admin/issues"))
+}
+
+#[tracing::instrument(name = "Cancel scheduled issue", skip(pool))]
+async fn cancel_scheduled_issue(pool: &PgPool, issue_id: Uuid) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET status = $2
+        WHERE newsletter_issue_id = $1 AND status = $3
+        "#,
+        issue_id,
+        IssueStatus::Cancelled as _,
+        IssueStatus::Scheduled as _,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to cancel the issue.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Summaries of all issues, most recent first, or of a single one.
+#[tracing::instrument(name = "Get issue summaries", skip(pool))]
+async fn get_issue_summaries(
+    pool: &PgPool,
+    issue_id: Option<Uuid>,
+) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            n.name AS list_name,
+            u.username AS "author?",
+            i.published_at,
+            i.status AS "status: IssueStatus",
+            i.send_at,
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "pending!",
+            COUNT(d.outcome) FILTER (WHERE d.outcome = 'delivered') AS "delivered!",
+            COUNT(d.outcome) FILTER (WHERE d.outcome = 'failed') AS "failed!",
+            COUNT(d.outcome) FILTER (WHERE d.outcome = 'skipped') AS "skipped!"
+        FROM newsletter_issues i
+        JOIN newsletters n ON n.newsletter_id = i.newsletter_id
+        LEFT JOIN users u ON u.user_id = i.author_id
+        LEFT JOIN issue_deliveries d ON d.newsletter_issue_id = i.newsletter_issue_id
+        WHERE $1::uuid IS NULL OR i.newsletter_issue_id = $1
+        GROUP BY i.newsletter_issue_id, n.name, u.username
+        ORDER BY COALESCE(i.send_at, i.published_at) DESC
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve newsletter issues.")?;
+    Ok(issues)
+}
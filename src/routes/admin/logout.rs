@@ -0,0 +1,11 @@
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::HttpResponse;
+
+pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+    session.log_out();
+    session
+        .insert_flash("You have successfully logged out.")
+        .map_err(e500)?;
+    Ok(see_other("/login"))
+}
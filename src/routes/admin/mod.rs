@@ -0,0 +1,24 @@
+mod dashboard;
+mod drafts;
+mod exports;
+mod imports;
+mod issues;
+mod logout;
+mod password;
+mod segments;
+mod subscribers;
+
+pub use dashboard::admin_dashboard;
+pub use drafts::{
+    create_draft, edit_draft_form, new_draft_form, preview_issue, publish_draft, send_test_email,
+    update_draft,
+};
+pub use exports::export_subscribers;
+pub use imports::import_subscribers;
+pub use issues::{cancel_issue, issue_details, list_issues};
+pub use logout::log_out;
+pub use password::*;
+pub use segments::{create_segment, delete_segment, list_segments, tag_subscriber};
+pub use subscribers::{
+    delete_subscriber, list_subscribers, subscriber_details, update_subscriber,
+};
@@ -0,0 +1,66 @@
+use crate::authentication::{self, get_username, validate_credentials, AuthError, Credentials, UserId};
+use crate::configuration::PasswordSettings;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+pub async fn change_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<PasswordSettings>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return redirect_with_message(
+            &session,
+            "You entered two different new passwords - \
+            the field values must match.",
+        );
+    }
+    if let Err(reason) = settings.policy.check(&form.new_password) {
+        return redirect_with_message(&session, &reason);
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                redirect_with_message(&session, "The current password is incorrect.")
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    let params = settings.hashing.params().map_err(e500)?;
+    authentication::change_password(*user_id, form.0.new_password, &pool, params)
+        .await
+        .map_err(e500)?;
+    // Every session of the user is gone, the current one included. It is
+    // stored again under a fresh key once the session middleware persists
+    // the renewal, while the others stay logged out.
+    session.renew();
+    redirect_with_message(&session, "Your password has been changed.")
+}
+
+fn redirect_with_message(
+    session: &TypedSession,
+    message: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    session.insert_flash(message).map_err(e500)?;
+    Ok(see_other("/admin/password"))
+}
@@ -0,0 +1,245 @@
+use crate::segments::{normalize_tag, SegmentDefinition};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct SegmentFormData {
+    name: String,
+    definition: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagAction {
+    Add,
+    Remove,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagFormData {
+    email: String,
+    tag: String,
+    action: TagAction,
+}
+
+pub async fn list_segments(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let flash_html = match session.take_flash() {
+        Some(message) => format!("<p><i>{}</i></p>", htmlescape::encode_minimal(&message)),
+        None => String::new(),
+    };
+    let segments =
+        sqlx::query!(r#"SELECT segment_id, name, definition FROM segments ORDER BY name"#)
+            .fetch_all(&**pool)
+            .await
+            .context("Failed to retrieve the segments.")
+            .map_err(e500)?;
+    let mut rows = String::new();
+    for segment in &segments {
+        rows.push_str(&format!(
+            r#"<tr>
+            <td>{name}</td>
+            <td><code>{segment_id}</code></td>
+            <td><pre>{definition}</pre></td>
+            <td>
+                <form action="/admin/segments/{segment_id}/delete" method="post">
+                    <button type="submit">Delete</button>
+                </form>
+            </td>
+        </tr>
+        "#,
+            name = htmlescape::encode_minimal(&segment.name),
+            segment_id = segment.segment_id,
+            definition = htmlescape::encode_minimal(
+                &serde_json::to_string_pretty(&segment.definition).map_err(e500)?
+            ),
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Segments</title>
+</head>
+<body>
+    {flash_html}
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Id</th>
+            <th>Definition</th>
+            <th></th>
+        </tr>
+        {rows}
+    </table>
+    <h2>New segment</h2>
+    <form action="/admin/segments" method="post">
+        <label>Name
+            <input type="text" name="name" placeholder="Enter the segment name">
+        </label>
+        <br>
+        <label>Definition
+            <textarea name="definition" rows="8" cols="60"
+                placeholder='{{"all": [{{"tag": "rust"}}, {{"email_domain": "example.com"}}]}}'></textarea>
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <h2>Tag a subscriber</h2>
+    <form action="/admin/subscribers/tags" method="post">
+        <label>Email
+            <input type="email" name="email" placeholder="Enter an email address">
+        </label>
+        <label>Tag
+            <input type="text" name="tag" placeholder="Enter a tag">
+        </label>
+        <button type="submit" name="action" value="add">Add tag</button>
+        <button type="submit" name="action" value="remove">Remove tag</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Create a segment", skip(form, pool, session))]
+pub async fn create_segment(
+    form: web::Form<SegmentFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        session
+            .insert_flash("Segment names must have between 1 and 100 characters.")
+            .map_err(e500)?;
+        return Ok(see_other("/admin/segments"));
+    }
+    let definition = match SegmentDefinition::parse(&form.definition) {
+        Ok(definition) => definition,
+        Err(e) => {
+            session.insert_flash(&e).map_err(e500)?;
+            return Ok(see_other("/admin/segments"));
+        }
+    };
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO segments (segment_id, name, definition)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        definition.to_value(),
+    )
+    .execute(&**pool)
+    .await
+    .context("Failed to store the segment.")
+    .map_err(e500)?
+    .rows_affected()
+        == 1;
+    let message = if inserted {
+        "The segment has been saved."
+    } else {
+        "A segment with this name already exists."
+    };
+    session.insert_flash(message).map_err(e500)?;
+    Ok(see_other("/admin/segments"))
+}
+
+/// Issues already created with the segment keep their audience.
+#[tracing::instrument(name = "Delete a segment", skip(pool, session))]
+pub async fn delete_segment(
+    segment_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM segments WHERE segment_id = $1"#,
+        segment_id.into_inner()
+    )
+    .execute(&**pool)
+    .await
+    .context("Failed to delete the segment.")
+    .map_err(e500)?
+    .rows_affected()
+        == 1;
+    let message = if deleted {
+        "The segment has been deleted."
+    } else {
+        "Unknown segment."
+    };
+    session.insert_flash(message).map_err(e500)?;
+    Ok(see_other("/admin/segments"))
+}
+
+#[tracing::instrument(name = "Tag a subscriber", skip(form, pool, session))]
+pub async fn tag_subscriber(
+    form: web::Form<TagFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tag = match normalize_tag(&form.tag) {
+        Ok(tag) => tag,
+        Err(e) => {
+            session.insert_flash(&e).map_err(e500)?;
+            return Ok(see_other("/admin/segments"));
+        }
+    };
+    let subscriber = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1"#,
+        form.email.trim()
+    )
+    .fetch_optional(&**pool)
+    .await
+    .context("Failed to look up the subscriber.")
+    .map_err(e500)?;
+    let subscriber_id = match subscriber {
+        Some(subscriber) => subscriber.id,
+        None => {
+            session.insert_flash("Unknown subscriber.").map_err(e500)?;
+            return Ok(see_other("/admin/segments"));
+        }
+    };
+    let message = match form.action {
+        TagAction::Add => {
+            sqlx::query!(
+                r#"
+                INSERT INTO subscriber_tags (subscriber_id, tag) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+                "#,
+                subscriber_id,
+                tag
+            )
+            .execute(&**pool)
+            .await
+            .context("Failed to tag the subscriber.")
+            .map_err(e500)?;
+            format!("The subscriber has been tagged `{}`.", tag)
+        }
+        TagAction::Remove => {
+            sqlx::query!(
+                r#"DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = $2"#,
+                subscriber_id,
+                tag
+            )
+            .execute(&**pool)
+            .await
+            .context("Failed to untag the subscriber.")
+            .map_err(e500)?;
+            format!("The subscriber is no longer tagged `{}`.", tag)
+        }
+    };
+    session.insert_flash(&message).map_err(e500)?;
+    Ok(see_other("/admin/segments"))
+}
@@ -0,0 +1,489 @@
+use crate::authentication::UserId;
+use crate::domain::{SubscriberEmail, SubscriberLocale, SubscriberName, SubscriberStatus};
+use crate::lists::{resolve_list_id, transition_membership, MembershipError};
+use crate::personal_data::{erase_subscriber, ErasureRequester};
+use crate::routes::{confirm_subscriber, error_chain_fmt};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberQuery {
+    /// Only subscribers with a membership in this status.
+    status: Option<String>,
+    /// Only subscribers that are members of this list.
+    list_id: Option<Uuid>,
+    /// Only subscribers whose address contains this, ignoring case.
+    email: Option<String>,
+    limit: Option<i64>,
+    /// The `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+/// Fields left out are not changed. `status` applies to the membership in
+/// `list_id`, or in the default list.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriberUpdate {
+    email: Option<String>,
+    name: Option<String>,
+    locale: Option<String>,
+    status: Option<String>,
+    list_id: Option<Uuid>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    locale: Option<String>,
+    /// Whether the address itself was ever confirmed.
+    address_confirmed: bool,
+    subscribed_at: DateTime<Utc>,
+    memberships: Vec<Membership>,
+    tags: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Membership {
+    list_id: Uuid,
+    list_name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    /// When the membership was last confirmed, if known.
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    /// `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Unknown subscriber.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscriberApiError::NotFound => StatusCode::NOT_FOUND,
+            SubscriberApiError::Conflict(_) => StatusCode::CONFLICT,
+            SubscriberApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            SubscriberApiError::UnexpectedError(_) => "Something went wrong.".to_string(),
+            e => e.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(serde_json::json!({ "error": message }))
+    }
+}
+
+/// Newest subscribers first.
+#[tracing::instrument(name = "List subscribers", skip(query, pool))]
+pub async fn list_subscribers(
+    query: web::Query<SubscriberQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberApiError> {
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(SubscriberApiError::ValidationError(format!(
+            "`limit` must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let status = query
+        .status
+        .as_deref()
+        .map(SubscriberStatus::parse)
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(decode_cursor)
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+    let email_pattern = query
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(|e| format!("%{}%", escape_like(e)));
+
+    let mut rows = sqlx::query!(
+        r#"
+        SELECT s.id, s.email, s.name, s.locale, s.address_confirmed, s.subscribed_at
+        FROM subscriptions s
+        WHERE ($1::text IS NULL OR s.email ILIKE $1)
+            AND (
+                ($2::text IS NULL AND $3::uuid IS NULL)
+                OR EXISTS (
+                    SELECT 1 FROM newsletter_subscriptions m
+                    WHERE m.subscriber_id = s.id
+                        AND ($2::text IS NULL OR m.status = $2)
+                        AND ($3::uuid IS NULL OR m.newsletter_id = $3)
+                )
+            )
+            AND (
+                $4::timestamptz IS NULL
+                OR (s.subscribed_at, s.id) < ($4::timestamptz, $5::uuid)
+            )
+        ORDER BY s.subscribed_at DESC, s.id DESC
+        LIMIT $6
+        "#,
+        email_pattern,
+        status.map(|s| s.as_str()),
+        query.list_id,
+        cursor.map(|(subscribed_at, _)| subscribed_at),
+        cursor.map(|(_, id)| id),
+        limit + 1
+    )
+    .fetch_all(&**pool)
+    .await
+    .context("Failed to retrieve the subscribers.")?;
+
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|r| encode_cursor(r.subscribed_at, r.id))
+    } else {
+        None
+    };
+    let subscribers = rows
+        .into_iter()
+        .map(|r| Subscriber {
+            id: r.id,
+            email: r.email,
+            name: r.name,
+            locale: r.locale,
+            address_confirmed: r.address_confirmed,
+            subscribed_at: r.subscribed_at,
+            memberships: vec![],
+            tags: vec![],
+        })
+        .collect();
+    let subscribers = with_memberships_and_tags(&pool, subscribers).await?;
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(pool))]
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberApiError> {
+    let subscriber = get_subscriber(&pool, subscriber_id.into_inner())
+        .await?
+        .ok_or(SubscriberApiError::NotFound)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Status changes go through the same transitions as the public flows:
+/// e.g. a complaint cannot be undone from here either.
+#[tracing::instrument(name = "Update a subscriber", skip(update, pool))]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    update: web::Json<SubscriberUpdate>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberApiError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let update = update.into_inner();
+    let email = update
+        .email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+    let name = update
+        .name
+        .map(SubscriberName::parse)
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+    let locale = update
+        .locale
+        .map(SubscriberLocale::parse)
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+    let status = update
+        .status
+        .as_deref()
+        .map(SubscriberStatus::parse)
+        .transpose()
+        .map_err(SubscriberApiError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET email = COALESCE($2, email),
+            name = COALESCE($3, name),
+            locale = COALESCE($4, locale)
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        email.as_ref().map(|e| e.as_ref()),
+        name.as_ref().map(|n| n.as_ref()),
+        locale.as_ref().map(|l| l.as_ref()),
+    );
+    let updated = match transaction.execute(query).await {
+        Ok(result) => result.rows_affected() == 1,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err(SubscriberApiError::Conflict(
+                "Another subscriber already uses this email address.".into(),
+            ));
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to update the subscriber.")
+                .into())
+        }
+    };
+    if !updated {
+        return Err(SubscriberApiError::NotFound);
+    }
+    if let Some(status) = status {
+        change_membership_status(&mut transaction, subscriber_id, update.list_id, status).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+
+    let subscriber = get_subscriber(&pool, subscriber_id)
+        .await?
+        .ok_or(SubscriberApiError::NotFound)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Erases the subscriber together with their memberships, tags, pending
+/// confirmation links and queued emails, as a subscriber can do through
+/// their personal data link. Their delivery history is anonymized.
+#[tracing::instrument(name = "Delete a subscriber", skip(pool, user_id))]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, SubscriberApiError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let requester = ErasureRequester::Admin(*user_id.into_inner());
+    if erase_subscriber(&mut transaction, subscriber_id.into_inner(), requester)
+        .await?
+        .is_none()
+    {
+        return Err(SubscriberApiError::NotFound);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn change_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+    status: SubscriberStatus,
+) -> Result<(), SubscriberApiError> {
+    let list_id = resolve_list_id(&mut **transaction, list_id)
+        .await
+        .context("Failed to look up the newsletter list.")?
+        .ok_or_else(|| SubscriberApiError::ValidationError("Unknown newsletter list.".into()))?;
+    let outcome = if status == SubscriberStatus::Confirmed {
+        confirm_subscriber(transaction, subscriber_id, list_id).await
+    } else {
+        transition_membership(transaction, list_id, subscriber_id, status)
+            .await
+            .map(|_| ())
+    };
+    outcome.map_err(|e| match e {
+        MembershipError::NotAMember => SubscriberApiError::Conflict(e.to_string()),
+        MembershipError::InvalidTransition(t) => SubscriberApiError::Conflict(t.to_string()),
+        MembershipError::UnexpectedError(e) => SubscriberApiError::UnexpectedError(e),
+    })
+}
+
+/// `None` if there is no such subscriber.
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, email, name, locale, address_confirmed, subscribed_at
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+    let Some(r) = row else {
+        return Ok(None);
+    };
+    let subscriber = Subscriber {
+        id: r.id,
+        email: r.email,
+        name: r.name,
+        locale: r.locale,
+        address_confirmed: r.address_confirmed,
+        subscribed_at: r.subscribed_at,
+        memberships: vec![],
+        tags: vec![],
+    };
+    Ok(with_memberships_and_tags(pool, vec![subscriber])
+        .await?
+        .pop())
+}
+
+async fn with_memberships_and_tags(
+    pool: &PgPool,
+    mut subscribers: Vec<Subscriber>,
+) -> Result<Vec<Subscriber>, anyhow::Error> {
+    let ids: Vec<Uuid> = subscribers.iter().map(|s| s.id).collect();
+    let mut memberships: HashMap<Uuid, Vec<Membership>> = HashMap::new();
+    let rows = sqlx::query!(
+        r#"
+        SELECT m.subscriber_id, m.newsletter_id, n.name, m.status, m.subscribed_at,
+            m.confirmed_at
+        FROM newsletter_subscriptions m
+        JOIN newsletters n ON n.newsletter_id = m.newsletter_id
+        WHERE m.subscriber_id = ANY($1)
+        ORDER BY n.name
+        "#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the memberships.")?;
+    for r in rows {
+        memberships
+            .entry(r.subscriber_id)
+            .or_default()
+            .push(Membership {
+                list_id: r.newsletter_id,
+                list_name: r.name,
+                status: r.status,
+                subscribed_at: r.subscribed_at,
+                confirmed_at: r.confirmed_at,
+            });
+    }
+    let mut tags: HashMap<Uuid, Vec<String>> = HashMap::new();
+    let rows = sqlx::query!(
+        r#"
+        SELECT subscriber_id, tag FROM subscriber_tags
+        WHERE subscriber_id = ANY($1)
+        ORDER BY tag
+        "#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the tags.")?;
+    for r in rows {
+        tags.entry(r.subscriber_id).or_default().push(r.tag);
+    }
+    for subscriber in &mut subscribers {
+        subscriber.memberships = memberships.remove(&subscriber.id).unwrap_or_default();
+        subscriber.tags = tags.remove(&subscriber.id).unwrap_or_default();
+    }
+    Ok(subscribers)
+}
+
+/// `%`, `_` and `\` in a search term match themselves.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn encode_cursor(subscribed_at: DateTime<Utc>, id: Uuid) -> String {
+    URL_SAFE_NO_PAD.encode(format!(
+        "{}|{}",
+        subscribed_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+        id
+    ))
+}
+
+fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid), String> {
+    let invalid = || "The cursor is invalid.".to_string();
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (subscribed_at, id) = decoded.split_once('|').ok_or_else(invalid)?;
+    let subscribed_at = DateTime::parse_from_rfc3339(subscribed_at).map_err(|_| invalid())?;
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+    Ok((subscribed_at.with_timezone(&Utc), id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_cursor, encode_cursor, escape_like};
+    use chrono::{TimeZone, Utc};
+    use claims::assert_err;
+    use uuid::Uuid;
+
+    #[test]
+    fn cursors_round_trip() {
+        let subscribed_at = Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap();
+        let id = Uuid::new_v4();
+        assert_eq!(
+            decode_cursor(&encode_cursor(subscribed_at, id)).unwrap(),
+            (subscribed_at, id)
+        );
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in ["", "not base64!", "bm8gc2VwYXJhdG9y", "YXxi"] {
+            assert_err!(decode_cursor(cursor), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+        assert_eq!(escape_like("ursula"), "ursula");
+    }
+}
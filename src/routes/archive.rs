@@ -0,0 +1,126 @@
+use crate::domain::IssueStatus;
+use crate::templating::{IssueContext, Template};
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Personal details are unknown to archive readers, placeholders are left blank.
+const ANONYMOUS_READER: IssueContext<'static> = IssueContext {
+    name: "",
+    email: "",
+    unsubscribe_url: "",
+};
+
+/// Renders stored templates, falling back to the raw content for issues
+/// published before templating was introduced.
+fn render_text(template: &str) -> String {
+    Template::parse(template, IssueContext::VARIABLES)
+        .map(|t| t.render_text(&ANONYMOUS_READER))
+        .unwrap_or_else(|_| template.to_string())
+}
+
+fn render_html(template: &str) -> String {
+    Template::parse(template, IssueContext::VARIABLES)
+        .map(|t| t.render_html(&ANONYMOUS_READER))
+        .unwrap_or_else(|_| template.to_string())
+}
+
+/// Public list of past issues, most recent first.
+pub async fn archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT i.newsletter_issue_id, i.title, i.published_at, n.name AS list_name
+        FROM newsletter_issues i
+        JOIN newsletters n ON n.newsletter_id = i.newsletter_id
+        WHERE i.status = $1
+        ORDER BY i.published_at DESC
+        "#,
+        IssueStatus::Published as _,
+    )
+    .fetch_all(&**pool)
+    .await
+    .context("Failed to retrieve past issues.")
+    .map_err(e500)?;
+    let mut items = String::new();
+    for issue in &issues {
+        items.push_str(&format!(
+            r#"<li>{} - <a href="/archive/{}">{}</a> ({})</li>
+        "#,
+            issue.published_at.format("%Y-%m-%d"),
+            issue.newsletter_issue_id,
+            htmlescape::encode_minimal(&render_text(&issue.title)),
+            htmlescape::encode_minimal(&issue.list_name),
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Archive</title>
+</head>
+<body>
+    <h1>Past issues</h1>
+    <ul>
+        {items}
+    </ul>
+</body>
+</html>"#,
+        )))
+}
+
+struct ArchivedIssue {
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+pub async fn archived_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT title, html_content, published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = $2
+        "#,
+        issue_id.into_inner(),
+        IssueStatus::Published as _,
+    )
+    .fetch_optional(&**pool)
+    .await
+    .context("Failed to retrieve the issue.")
+    .map_err(e500)?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{published_at}</p>
+    {content}
+    <p><a href="/archive">&lt;- All issues</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&render_text(&issue.title)),
+            published_at = issue.published_at.format("%Y-%m-%d"),
+            content = render_html(&issue.html_content),
+        )))
+}
@@ -0,0 +1,44 @@
+use crate::session_state::TypedSession;
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+
+pub async fn login_form(session: TypedSession) -> HttpResponse {
+    let error_html = match session.take_flash() {
+        Some(message) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(&message)
+        ),
+        None => String::new(),
+    };
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input
+                type="text"
+                placeholder="Enter Username"
+                name="username"
+            >
+        </label>
+        <label>Password
+            <input
+                type="password"
+                placeholder="Enter Password"
+                name="password"
+            >
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#,
+        ))
+}
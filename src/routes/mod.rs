@@ -3,9 +3,21 @@ mod subscriptions;
 mod subscriptions_confirm;
 mod newsletter;
 mod home;
+mod login;
+mod admin;
+mod unsubscribe;
+mod archive;
+mod webhooks;
+mod personal_data;
 
 pub use health_check::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
 pub use newsletter::*;
-pub use home::*;
\ No newline at end of file
+pub use home::*;
+pub use login::*;
+pub use admin::*;
+pub use unsubscribe::*;
+pub use archive::*;
+pub use webhooks::*;
+pub use personal_data::*;
\ No newline at end of file
@@ -1,32 +1,53 @@
 use actix_web::{HttpResponse, web, ResponseError, HttpRequest};
 use actix_web::http::{header, StatusCode};
 use actix_web::http::header::{HeaderMap, HeaderValue};
-use anyhow::{anyhow, Context, Error};
+use anyhow::{Context, Error};
 use base64::Engine;
-use secrecy::{Secret, ExposeSecret};
-use sqlx::PgPool;
-use sha3::Digest;
-use argon2::{Algorithm, Argon2, Version, Params, PasswordHasher, PasswordHash, PasswordVerifier};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::Secret;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::domain::{IssueContent, IssueStatus, IssueTitle};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::lists::resolve_list_id;
+use crate::segments::{get_segment, Segment};
 use crate::routes::subscriptions::error_chain_fmt;
-use crate::telemetry::spawn_blocking_with_tracing;
+
+/// How far in the past a `send_at` may be, to allow for clock skew between
+/// the client and us. Such an issue is published straight away.
+const SEND_AT_TOLERANCE_SECONDS: i64 = 60;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
-    content: Content
+    content: Content,
+    /// The list to publish to, the default one if missing.
+    list_id: Option<Uuid>,
+    /// Deliver the issue at a later time rather than straight away.
+    send_at: Option<DateTime<Utc>>,
+    /// Only deliver to the list members matching this saved segment.
+    segment_id: Option<Uuid>,
+}
+
+#[derive(serde::Serialize)]
+struct PublishResponse {
+    newsletter_issue_id: Uuid,
+    status: IssueStatus,
 }
 
 #[derive(serde::Deserialize)]
 pub struct Content {
     html: String,
-    text: String
+    /// Generated from `html` if missing.
+    text: Option<String>,
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error("Authentication failed")]
     AuthError(#[source] Error),
     #[error(transparent)]
@@ -44,6 +65,9 @@ impl ResponseError for PublishError {
     
     fn error_response(&self) -> HttpResponse {
         match self {
+            PublishError::ValidationError(_) => {
+                HttpResponse::BadRequest().body(self.to_string())
+            },
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             },
@@ -62,92 +86,152 @@ impl ResponseError for PublishError {
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request)
+    skip(body, pool, request)
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     request: HttpRequest
 ) -> Result<HttpResponse, PublishError> {
     let credentials = basic_authentication(request.headers())
         .map_err(PublishError::AuthError)?;
     tracing::Span::current().record(
         "username",
-        &tracing::field::display(&credentials.username),
+        tracing::field::display(&credentials.username),
     );
-    let user_id = validate_credentials(credentials, &pool).await?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-    let subscribers: Vec<Result<ConfirmedSubscriber, Error>> = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.html,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to send newsletter issue to {}", 
-                            subscriber.email
-                        )
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "Skipping a confirmed subscriber \
-                     Their stored contact details are invalid"
-                )
-            }
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let idempotency_key = idempotency_key(request.headers())
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+    let BodyData { title, content, list_id, send_at, segment_id } = body.0;
+    let title = IssueTitle::parse(title).map_err(PublishError::ValidationError)?;
+    let content =
+        IssueContent::parse(content.html, content.text).map_err(PublishError::ValidationError)?;
+    let list_id = resolve_list_id(&**pool, list_id)
+        .await
+        .context("Failed to look up the newsletter list.")?
+        .ok_or_else(|| PublishError::ValidationError("Unknown newsletter list.".into()))?;
+    let segment = match segment_id {
+        Some(segment_id) => Some(
+            get_segment(&**pool, segment_id)
+                .await
+                .context("Failed to look up the segment.")?
+                .ok_or_else(|| PublishError::ValidationError("Unknown segment.".into()))?,
+        ),
+        None => None,
+    };
+    let now = Utc::now();
+    if send_at.is_some_and(|send_at| send_at < now - Duration::seconds(SEND_AT_TOLERANCE_SECONDS)) {
+        return Err(PublishError::ValidationError(format!(
+            "send_at cannot be more than {} seconds in the past.",
+            SEND_AT_TOLERANCE_SECONDS
+        )));
+    }
+    let send_at = send_at.filter(|send_at| *send_at > now);
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, user_id).await? {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            return Ok(saved_response);
         }
+    };
+    let (issue_id, status) = insert_newsletter_issue(
+        &mut transaction,
+        list_id,
+        user_id,
+        &title,
+        &content,
+        send_at,
+        segment.as_ref(),
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    // Scheduled issues are enqueued by the `issue_scheduler` once due.
+    if send_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id, list_id)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
     }
-    Ok(HttpResponse::Ok().finish())
+    let response = HttpResponse::Accepted().json(PublishResponse {
+        newsletter_issue_id: issue_id,
+        status,
+    });
+    let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+    Ok(response)
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// Required: without one, a retried request would send the issue again.
+fn idempotency_key(headers: &HeaderMap) -> Result<IdempotencyKey, anyhow::Error> {
+    let key = headers
+        .get("Idempotency-Key")
+        .context("The 'Idempotency-Key' header was missing")?
+        .to_str()
+        .context("The 'Idempotency-Key' header was not a valid UTF8 string")?;
+    IdempotencyKey::try_from(key.to_string())
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, Error>>, Error> {
-    
-    struct Row {
-        email: String,
-    }
-
-    let confirmed_subscribers  = sqlx::query!(
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    author_id: Uuid,
+    title: &IssueTitle,
+    content: &IssueContent,
+    send_at: Option<DateTime<Utc>>,
+    segment: Option<&Segment>,
+) -> Result<(Uuid, IssueStatus), sqlx::Error> {
+    let status = if send_at.is_some() {
+        IssueStatus::Scheduled
+    } else {
+        IssueStatus::Published
+    };
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
         r#"
-            SELECT email
-            FROM subscriptions
-            WHERE status = 'confirmed'
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            newsletter_id,
+            author_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            status,
+            send_at,
+            segment_id,
+            segment_definition
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, $6,
+            COALESCE($7, now()),
+            $8,
+            $7,
+            $9,
+            $10
+        )
         "#,
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber {email}),
-        Err(error) => Err(anyhow::anyhow!(error))
-    })
-    .collect();  
-
-    Ok(confirmed_subscribers)
-}
-
-struct Credentials {
-    username: String,
-    password: Secret<String>
+        newsletter_issue_id,
+        list_id,
+        author_id,
+        title.as_ref(),
+        content.text(),
+        content.html(),
+        send_at,
+        status as _,
+        segment.map(|segment| segment.segment_id),
+        segment.map(|segment| segment.definition.to_value()),
+    );
+    transaction.execute(query).await?;
+    Ok((newsletter_issue_id, status))
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+pub(crate) fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
     let header_value = headers
         .get("Authorization")
         .context("The 'Authorization' header was missing")?
@@ -186,85 +270,3 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
         password: Secret::new(password)
     })
 }
-
-#[tracing::instrument(name = "Validate credentials", skip(credentials))]
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool
-) -> Result<uuid::Uuid, PublishError> {
-
-    let mut user_id: Option<Uuid> = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-                    .to_string()
-    );
-
-    if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(&credentials.username, &pool)
-            .await
-            .map_err(PublishError::UnexpectedError)?
-    {
-        user_id = Some(stored_user_id);
-        expected_password_hash = stored_password_hash
-    };
-
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(
-            expected_password_hash,
-            credentials.password
-        )
-    })
-    .await
-    .context("Failed to spawn blocking task..")
-    .map_err(PublishError::UnexpectedError)??;
-    
-    user_id.ok_or_else(|| 
-        PublishError::AuthError(anyhow::anyhow!("Unknown username."))
-    )
-}
-
-#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
-async fn get_stored_credentials(
-    username: &str,
-    pool: &PgPool
-) -> Result<Option<(uuid::Uuid, Secret<String>)>, Error> {
-    let row = sqlx::query!(
-        r#"
-        SELECT user_id, password_hash
-        FROM users
-        WHERE username = $1
-        "#,
-        username,
-    )
-        .fetch_optional(pool)
-        .await
-        .context("Failed to perform a query to retrieve stored credentials")?
-        .map(|row| (row.user_id, Secret::new(row.password_hash)));
-    
-    Ok(row)
-}
-
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_password_hash, password_candidate)
-)]
-fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>
-) -> Result<(), PublishError> {
-    let expected_password_hash = PasswordHash::new(
-        &expected_password_hash.expose_secret()
-    )
-    .context("Failed to parse hash in PHC string format.")
-    .map_err(PublishError::UnexpectedError)?;
-
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash
-        )
-        .context("Invalid password.")
-        .map_err(PublishError::UnexpectedError)
-}
\ No newline at end of file
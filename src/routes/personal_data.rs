@@ -0,0 +1,205 @@
+use super::subscriptions_confirm::page;
+use crate::domain::SubscriberEmail;
+use crate::email_queue::enqueue_personal_data_email;
+use crate::personal_data::{collect_personal_data, erase_subscriber, hash_token, ErasureRequester};
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::{self, ContentType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use std::fmt::Formatter;
+use uuid::Uuid;
+
+/// How long the links in a personal data email can be used for.
+const TOKEN_TTL: chrono::Duration = chrono::Duration::hours(24);
+
+#[derive(serde::Deserialize)]
+pub struct PersonalDataRequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PersonalDataParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PersonalDataError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The personal data token is unknown.")]
+    UnknownToken,
+    #[error("The personal data token has expired.")]
+    Expired,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PersonalDataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PersonalDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PersonalDataError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PersonalDataError::UnknownToken => StatusCode::UNAUTHORIZED,
+            PersonalDataError::Expired => StatusCode::GONE,
+            PersonalDataError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            PersonalDataError::ValidationError(e) => e.as_str(),
+            PersonalDataError::UnknownToken => {
+                "This link is not valid. \
+                Please check that you copied it in full."
+            }
+            PersonalDataError::Expired => {
+                "This link has expired. \
+                Please request a new one."
+            }
+            PersonalDataError::UnexpectedError(_) => {
+                "Something went wrong on our side. Please try again later."
+            }
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(page("Personal data", &htmlescape::encode_minimal(message)))
+    }
+}
+
+/// Queues an email with a link to download, and one to erase, everything
+/// we hold about the address. Known and unknown addresses go through the
+/// same work and get the same response, so that it cannot be used to find
+/// out who is subscribed.
+#[tracing::instrument(name = "Request personal data", skip(form, pool))]
+pub async fn request_personal_data(
+    form: web::Form<PersonalDataRequestFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(PersonalDataError::ValidationError)?;
+    enqueue_personal_data_email(&**pool, &email)
+        .await
+        .context("Failed to queue the personal data email.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Everything we hold about the subscriber, as a JSON download.
+#[tracing::instrument(name = "Download personal data", skip(parameters, pool))]
+pub async fn download_personal_data(
+    parameters: web::Query<PersonalDataParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    let subscriber_id = verify_token(&**pool, &parameters.token).await?;
+    let data = collect_personal_data(&pool, subscriber_id)
+        .await?
+        .ok_or(PersonalDataError::UnknownToken)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"personal-data.json\"",
+        ))
+        .json(data))
+}
+
+/// Asks the subscriber to confirm. Nothing changes on a `GET`, so that
+/// link scanners following the link do not erase anybody.
+#[tracing::instrument(name = "Show the erasure page", skip(parameters, pool))]
+pub async fn erasure_form(
+    parameters: web::Query<PersonalDataParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    verify_token(&**pool, &parameters.token).await?;
+    let action = format!(
+        "/subscriptions/personal-data/erase?token={}",
+        parameters.token
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>Do you want us to erase everything we hold about you?
+    You will be unsubscribed from every newsletter. This cannot be undone.</p>
+    <form action="{}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&action)
+        )))
+}
+
+/// Erases the subscriber. Only an audit record without any personal data
+/// is kept.
+#[tracing::instrument(
+    name = "Erase personal data",
+    skip(parameters, pool),
+    fields(erasure_id = tracing::field::Empty)
+)]
+pub async fn erase_personal_data(
+    parameters: web::Query<PersonalDataParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PersonalDataError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = verify_token(&mut *transaction, &parameters.token).await?;
+    // A concurrent request may have erased the subscriber already.
+    let erasure_id = erase_subscriber(
+        &mut transaction,
+        subscriber_id,
+        ErasureRequester::Subscriber,
+    )
+    .await?
+    .ok_or(PersonalDataError::UnknownToken)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    tracing::Span::current().record("erasure_id", tracing::field::display(erasure_id));
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page(
+            "Data erased",
+            "Your data has been erased. You will not receive any further emails from us.",
+        )))
+}
+
+/// Returns the subscriber the token was sent to.
+async fn verify_token(
+    executor: impl PgExecutor<'_>,
+    token: &str,
+) -> Result<Uuid, PersonalDataError> {
+    struct StoredToken {
+        subscriber_id: Uuid,
+        created_at: DateTime<Utc>,
+    }
+
+    let token = sqlx::query_as!(
+        StoredToken,
+        r#"SELECT subscriber_id, created_at FROM personal_data_tokens WHERE token_hash = $1"#,
+        hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve the personal data token.")?
+    .ok_or(PersonalDataError::UnknownToken)?;
+    if token.created_at + TOKEN_TTL < Utc::now() {
+        return Err(PersonalDataError::Expired);
+    }
+    Ok(token.subscriber_id)
+}
@@ -1,6 +1,12 @@
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::domain::{
+    NewSubscriber, SubscriberEmail, SubscriberLocale, SubscriberName, SubscriberStatus,
+};
 use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::email_queue::enqueue_confirmation_email;
+use crate::email_templates::{EmailTemplate, EmailTemplates, Recipient};
+use crate::lists::{
+    get_membership_status, insert_pending_membership, resolve_list_id, set_membership_status,
+};
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -17,6 +23,10 @@ use uuid::Uuid;
 pub struct FormData {
     email : String,
     name: String,
+    /// The list to subscribe to, the default one if missing.
+    list_id: Option<Uuid>,
+    /// The language of the emails we send, the default one if missing.
+    locale: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -25,7 +35,12 @@ impl TryFrom<FormData> for NewSubscriber {
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         let name: SubscriberName   = SubscriberName::parse(value.name)?;
         let email: SubscriberEmail = SubscriberEmail::parse(value.email)?;
-        Ok(Self {email, name})
+        let locale = value
+            .locale
+            .filter(|locale| !locale.trim().is_empty())
+            .map(SubscriberLocale::parse)
+            .transpose()?;
+        Ok(Self {email, name, locale})
     }
 }
 
@@ -62,7 +77,7 @@ impl From<anyhow::Error> for SubscriberError {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -71,44 +86,177 @@ impl From<anyhow::Error> for SubscriberError {
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscriberError> {
-    let new_subscriber = form.0.try_into().map_err(SubscriberError::ValidationError)?;
+    let list_id = form.list_id;
+    let new_subscriber: NewSubscriber = form.0.try_into().map_err(SubscriberError::ValidationError)?;
 
     let mut transaction: Transaction<Postgres> = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id: Uuid =  insert_subscriber(
-        &mut transaction, 
-        &new_subscriber
-    )
-    .await
-    .context("Failed to insert new subscriber in the database.")?;
+    let list_id = resolve_list_id(&mut *transaction, list_id)
+        .await
+        .context("Failed to look up the newsletter list.")?
+        .ok_or_else(|| SubscriberError::ValidationError("Unknown newsletter list.".into()))?;
 
-    let subscription_token: String= generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    // A concurrent signup with the same address may insert it first.
+    let inserted = insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to insert new subscriber in the database.")?;
+    let (subscriber_id, requested_locale) = match inserted {
+        Some(subscriber_id) => (subscriber_id, None),
+        None => {
+            let subscriber_id = get_subscriber_by_email(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look up the subscriber in the database.")?
+                .context("The subscriber disappeared while signing up.")?;
+            // Only applied once the link is used, see `subscription_tokens`.
+            (subscriber_id, new_subscriber.locale.as_ref())
+        }
+    };
+
+    // The response is the same whatever we already know about the address,
+    // and takes as long, so that it cannot be used to find out who is
+    // subscribed.
+    match get_membership_status(&mut transaction, list_id, subscriber_id)
+        .await
+        .context("Failed to look up the subscriber's membership.")?
+    {
+        None => insert_pending_membership(&mut transaction, list_id, subscriber_id)
+            .await
+            .context("Failed to store the subscriber's membership.")?,
+        // Pending members get a fresh link.
+        Some(SubscriberStatus::PendingConfirmation) => {}
+        // Members who left or bounced have to confirm again before
+        // receiving any issue.
+        Some(status) if status.can_transition_to(SubscriberStatus::PendingConfirmation) => {
+            set_membership_status(
+                &mut transaction,
+                list_id,
+                subscriber_id,
+                status,
+                SubscriberStatus::PendingConfirmation,
+            )
+            .await
+            .context("Failed to store the subscriber's membership.")?;
+        }
+        // Confirmed members have nothing to do, complaints are final: the
+        // worker drops their email.
+        Some(_) => {}
+    }
+
+    enqueue_confirmation_email(
+        &mut transaction,
+        subscriber_id,
+        list_id,
+        requested_locale.map(|locale| locale.as_ref()),
+    )
+    .await
+    .context("Failed to queue the confirmation email.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber")?;
-    
-    send_confirmation_email(
-        &email_client, 
-        new_subscriber, 
-        &base_url.0,
-        &subscription_token
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendConfirmationFormData {
+    email: String,
+    list_id: Option<Uuid>,
+}
+
+/// Sends a fresh confirmation link to a subscriber who has not confirmed
+/// yet, e.g. because the previous one expired.
+/// The response is the same whether or not the address is pending, so it
+/// cannot be used to find out who is subscribed.
+#[tracing::instrument(
+    name = "Resending a confirmation email",
+    skip(form, pool),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendConfirmationFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let email = SubscriberEmail::parse(form.email.clone())
+        .map_err(SubscriberError::ValidationError)?;
+
+    let mut transaction: Transaction<Postgres> = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let list_id = resolve_list_id(&mut *transaction, form.list_id)
+        .await
+        .context("Failed to look up the newsletter list.")?
+        .ok_or_else(|| SubscriberError::ValidationError("Unknown newsletter list.".into()))?;
+
+    let (subscriber_id, requested_locale) =
+        match get_pending_subscriber_id(&mut transaction, list_id, &email)
+            .await
+            .context("Failed to look up the subscriber.")?
+        {
+            Some(pending) => pending,
+            None => return Ok(HttpResponse::Ok().finish()),
+        };
+
+    enqueue_confirmation_email(
+        &mut transaction,
+        subscriber_id,
+        list_id,
+        requested_locale.as_deref(),
     )
-     .await
-     .context("Failed to send a confirmation email. ")?;
+        .await
+        .context("Failed to queue the confirmation email.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to queue a confirmation email")?;
     Ok(HttpResponse::Ok().finish())
 }
 
+#[tracing::instrument(
+    name = "Get pending subscriber id",
+    skip(transaction, email)
+)]
+/// Returns the pending member, along with the locale requested by the link
+/// last sent to them, so that a new link keeps it.
+async fn get_pending_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, Option<String>)>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            s.id,
+            (
+                SELECT t.locale
+                FROM subscription_tokens t
+                WHERE t.subscriber_id = s.id AND t.newsletter_id = m.newsletter_id
+                ORDER BY t.created_at DESC
+                LIMIT 1
+            ) AS locale
+        FROM subscriptions s
+        JOIN newsletter_subscriptions m ON m.subscriber_id = s.id
+        WHERE
+            s.email = $1 AND
+            m.newsletter_id = $2 AND
+            m.status = $3
+        "#,
+        email.as_ref(),
+        list_id,
+        SubscriberStatus::PendingConfirmation.as_str()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result.map(|r| (r.id, r.locale)))
+}
+
 
 
 pub struct StoreTokenError(sqlx::Error);
@@ -137,14 +285,15 @@ impl std::fmt::Debug for StoreTokenError {
 
 #[tracing::instrument(
     name = "Send a confirmation email to our new subscriber",
-    skip(email_client, new_subscriber),
+    skip(email_client, templates, recipient),
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    templates: &EmailTemplates,
+    recipient: &Recipient,
     base_url: &str,
     subscription_token: &str
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
 
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}", 
@@ -152,27 +301,34 @@ pub async fn send_confirmation_email(
         subscription_token
     );
     
-    let plain_body = format!(
-        "Welcome to our newsletter! \n Visit {} to confirm your subscription.",
-        confirmation_link
-    );
-    
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-        Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-    
-    email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &html_body,
-            &plain_body
+    templates
+        .send(
+            email_client,
+            EmailTemplate::Confirmation,
+            recipient,
+            &[("confirmation_link", &confirmation_link)],
         )
         .await
 }
 
+#[tracing::instrument(
+    name = "Get subscriber by email",
+    skip(transaction, email)
+)]
+async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+/// Returns `None` if the address is known already.
 #[tracing::instrument(
     name = "Saving new subscriber details in database",
     skip(new_subscriber, transaction)
@@ -180,27 +336,27 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    
-        let subscriber_id: Uuid = Uuid::new_v4();
-
-        let query = sqlx::query!(
-            r#"
-                INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-                VALUES ($1, $2, $3, $4, 'pending_confirmation')
-            "#,
-            subscriber_id,
-            new_subscriber.email.as_ref(),
-            new_subscriber.name.as_ref(),
-            Utc::now(),
-        );
-        transaction.execute(query)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to execute query: {:?}", e);
-                e
-            })?;
-        Ok(subscriber_id)
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, address_confirmed, locale)
+        VALUES ($1, $2, $3, $4, false, $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        Utc::now(),
+        new_subscriber.locale.as_ref().map(|locale| locale.as_ref()),
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(inserted.map(|r| r.id))
 }
 
 #[tracing::instrument(
@@ -210,13 +366,17 @@ pub async fn insert_subscriber(
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
+    requested_locale: Option<&str>,
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, newsletter_id, locale)
+        VALUES ($1, $2, $3, $4)"#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id,
+        requested_locale,
     );
     transaction.execute(query)
         .await
@@ -227,7 +387,7 @@ pub async fn store_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
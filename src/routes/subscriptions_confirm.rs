@@ -1,5 +1,16 @@
-use actix_web::{HttpResponse, web};
-use sqlx::{PgPool};
+use crate::configuration::WelcomeEmailSettings;
+use crate::domain::SubscriberStatus;
+use crate::email_queue::enqueue_welcome_email;
+use crate::lists::{transition_membership, MembershipError};
+use crate::routes::error_chain_fmt;
+use crate::startup::ConfirmationTokenTtl;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::fmt::Formatter;
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -7,74 +18,247 @@ pub struct Parameters {
     subscription_token: String,
 }
 
+#[derive(thiserror::Error)]
+pub enum ConfirmationError {
+    #[error("The subscription token is unknown.")]
+    UnknownToken,
+    #[error("The subscription has already been confirmed with this token.")]
+    AlreadyConfirmed,
+    #[error("The subscription token has expired.")]
+    Expired,
+    #[error("The subscription can no longer be confirmed with this token.")]
+    NoLongerPending,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmationError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmationError::AlreadyConfirmed => StatusCode::CONFLICT,
+            ConfirmationError::Expired => StatusCode::GONE,
+            ConfirmationError::NoLongerPending => StatusCode::CONFLICT,
+            ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            ConfirmationError::UnknownToken => {
+                "This confirmation link is not valid. \
+                Please check that you copied it in full."
+            }
+            ConfirmationError::AlreadyConfirmed => {
+                "This confirmation link has already been used. \
+                Your subscription is confirmed."
+            }
+            ConfirmationError::Expired => {
+                "This confirmation link has expired. \
+                Please request a new one."
+            }
+            ConfirmationError::NoLongerPending => {
+                "This confirmation link is no longer valid. \
+                Please subscribe again."
+            }
+            ConfirmationError::UnexpectedError(_) => {
+                "Something went wrong on our side. Please try again later."
+            }
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(page("Subscription not confirmed", message))
+    }
+}
+
 #[tracing::instrument(
    name = "Confirm a pending subscriber", 
-    skip(parameters)
+    skip(parameters, pool, ttl, welcome_email)
 )]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
-) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(
-        &pool,
-        &parameters.subscription_token,
-    ).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish()
-    };
-    
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err(){
-                return HttpResponse::InternalServerError().finish();
+    ttl: web::Data<ConfirmationTokenTtl>,
+    welcome_email: web::Data<WelcomeEmailSettings>,
+) -> Result<HttpResponse, ConfirmationError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscription token.")?
+        .ok_or(ConfirmationError::UnknownToken)?;
+
+    if token.consumed_at.is_some() {
+        return Err(ConfirmationError::AlreadyConfirmed);
+    }
+    if token.is_expired(ttl.0) {
+        return Err(ConfirmationError::Expired);
+    }
+
+    consume_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to mark the subscription token as consumed.")?;
+    confirm_subscriber(&mut transaction, token.subscriber_id, token.newsletter_id)
+        .await
+        .map_err(|e| match e {
+            // Another, more recent link was used already.
+            MembershipError::InvalidTransition(t) if t.from == SubscriberStatus::Confirmed => {
+                ConfirmationError::AlreadyConfirmed
             }
-            HttpResponse::Ok().finish()
+            MembershipError::InvalidTransition(_) => ConfirmationError::NoLongerPending,
+            e => ConfirmationError::UnexpectedError(
+                anyhow::Error::new(e).context("Failed to mark the subscriber as confirmed."),
+            ),
+        })?;
+    if let Some(locale) = &token.locale {
+        update_locale(&mut transaction, token.subscriber_id, locale)
+            .await
+            .context("Failed to update the subscriber's locale.")?;
+    }
+    if welcome_email.enabled {
+        enqueue_welcome_email(&mut transaction, token.subscriber_id, token.newsletter_id)
+            .await
+            .context("Failed to enqueue the welcome email.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page(
+            "Subscription confirmed",
+            "Thank you! Your subscription is confirmed.",
+        )))
+}
+
+pub(crate) fn page(title: &str, message: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <p>{message}</p>
+</body>
+</html>"#
+    )
+}
+
+struct StoredToken {
+    subscriber_id: Uuid,
+    newsletter_id: Uuid,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    /// Asked for by a repeat signup, applied once the address owner
+    /// confirms.
+    locale: Option<String>,
+}
+
+impl StoredToken {
+    fn is_expired(&self, ttl: std::time::Duration) -> bool {
+        match chrono::Duration::from_std(ttl) {
+            Ok(ttl) => self.created_at + ttl < Utc::now(),
+            // A TTL too large to be represented never expires.
+            Err(_) => false,
         }
     }
-    
 }
 
 #[tracing::instrument(
     name = "Mark subscriber as confirmed",
-    skip(subscriber_id, pool),
+    skip(subscriber_id, list_id, transaction),
 )]
 pub async fn confirm_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), MembershipError> {
+    transition_membership(transaction, list_id, subscriber_id, SubscriberStatus::Confirmed)
+        .await?;
+    mark_address_confirmed(transaction, subscriber_id)
+        .await
+        .context("Failed to mark the address as confirmed.")?;
+
+    Ok(())
+}
+
+/// Records that the address was confirmed, which only happens once, for
+/// whichever list comes first.
+pub async fn mark_address_confirmed(
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET address_confirmed = true WHERE id = $1"#,
         subscriber_id,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Update subscriber locale",
+    skip(transaction, locale)
+)]
+async fn update_locale(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    locale: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET locale = $2 WHERE id = $1"#,
+        subscriber_id,
+        locale
+    );
+    transaction.execute(query).await?;
     Ok(())
 }
 
 #[tracing::instrument(
-    name = "Get subscriber_id from token",
-    skip(subscription_token, pool),
+    name = "Mark subscription token as consumed",
+    skip(subscription_token, transaction),
 )]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"UPDATE subscription_tokens SET consumed_at = now() WHERE subscription_token = $1"#,
+        subscription_token,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Locks the token row, so that concurrent requests cannot both use it.
+#[tracing::instrument(
+    name = "Get subscription token",
+    skip(subscription_token, transaction),
+)]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        StoredToken,
+        "SELECT subscriber_id, newsletter_id, created_at, consumed_at, locale \
+        FROM subscription_tokens \
+        WHERE subscription_token = $1 FOR UPDATE",
         subscription_token
     )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
-}
\ No newline at end of file
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result)
+}
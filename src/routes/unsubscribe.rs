@@ -0,0 +1,138 @@
+use crate::domain::{SubscriberStatus, UnsubscribeToken};
+use crate::email_queue::enqueue_unsubscribe_email;
+use crate::lists::{transition_membership, MembershipError};
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Asks the subscriber to confirm. Nothing changes on a `GET`, so that
+/// link scanners following the link do not unsubscribe anybody.
+#[tracing::instrument(name = "Show the unsubscribe page", skip(parameters, secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let action = format!("/subscriptions/unsubscribe?token={}", parameters.token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving this newsletter?</p>
+    <form action="{}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&action)
+        )))
+}
+
+/// Handles both the form above and RFC 8058 one-click requests sent by
+/// mail clients, which POST `List-Unsubscribe=One-Click` to the link.
+/// The first request also queues an acknowledgement email.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, secret),
+    fields(
+        subscriber_id = tracing::field::Empty,
+        list_id = tracing::field::Empty
+    )
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let (subscriber_id, list_id) = UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    tracing::Span::current()
+        .record("subscriber_id", tracing::field::display(subscriber_id))
+        .record("list_id", tracing::field::display(list_id));
+    mark_subscriber_as_unsubscribed(&pool, subscriber_id, list_id)
+        .await
+        .context("Failed to unsubscribe the subscriber.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive any further issues.</p>
+</body>
+</html>"#,
+        ))
+}
+
+/// Queues the acknowledgement with the status change, unless the subscriber
+/// had already left the list or is not receiving it anyway.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    match transition_membership(
+        &mut transaction,
+        list_id,
+        subscriber_id,
+        SubscriberStatus::Unsubscribed,
+    )
+    .await
+    {
+        Ok(_) => {
+            enqueue_unsubscribe_email(&mut transaction, subscriber_id, list_id).await?;
+            transaction.commit().await?;
+            Ok(())
+        }
+        Err(MembershipError::NotAMember | MembershipError::InvalidTransition(_)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
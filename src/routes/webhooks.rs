@@ -0,0 +1,276 @@
+use crate::configuration::PostmarkWebhookSettings;
+use crate::domain::SubscriberStatus;
+use crate::lists::set_membership_status;
+use crate::routes::{basic_authentication, error_chain_fmt};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The events we subscribe to in the Postmark webhook settings, keyed by
+/// their `RecordType`.
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    Bounce(Bounce),
+    SpamComplaint(Bounce),
+    Delivery(Delivery),
+    /// Opens, clicks and the like are acknowledged and dropped.
+    #[serde(other)]
+    Unsupported,
+}
+
+/// Postmark reports spam complaints with the same fields as bounces.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Bounce {
+    r#type: String,
+    email: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    bounced_at: DateTime<Utc>,
+}
+
+impl Bounce {
+    /// Permanent failures, the address will never accept our emails.
+    fn is_hard(&self) -> bool {
+        matches!(self.r#type.as_str(), "HardBounce" | "BadEmailAddress")
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Delivery {
+    recipient: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    delivered_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="webhooks""#),
+                );
+                response
+            }
+        }
+    }
+}
+
+/// Records bounce, spam complaint and delivery events sent by Postmark.
+/// Hard bounces and complaints also take the address off every list it is
+/// on, so that no further issue is sent to it.
+#[tracing::instrument(name = "Handle a Postmark webhook", skip_all)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    settings: web::Data<PostmarkWebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate(&request, &settings).map_err(WebhookError::AuthError)?;
+    let payload: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+    let event: PostmarkEvent = serde_json::from_value(payload.clone())
+        .map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    match &event {
+        PostmarkEvent::Bounce(bounce) => {
+            let event = NewEmailEvent {
+                event_type: "bounce",
+                bounce_type: Some(&bounce.r#type),
+                email: &bounce.email,
+                message_id: bounce.message_id.as_deref(),
+                occurred_at: bounce.bounced_at,
+            };
+            if !insert_email_event(&mut transaction, &event, &payload).await? {
+                return Ok(HttpResponse::Ok().finish());
+            }
+            if bounce.is_hard() {
+                mark_memberships(&mut transaction, &bounce.email, SubscriberStatus::Bounced)
+                    .await?;
+            }
+        }
+        PostmarkEvent::SpamComplaint(complaint) => {
+            let event = NewEmailEvent {
+                event_type: "spam_complaint",
+                bounce_type: Some(&complaint.r#type),
+                email: &complaint.email,
+                message_id: complaint.message_id.as_deref(),
+                occurred_at: complaint.bounced_at,
+            };
+            if !insert_email_event(&mut transaction, &event, &payload).await? {
+                return Ok(HttpResponse::Ok().finish());
+            }
+            mark_memberships(
+                &mut transaction,
+                &complaint.email,
+                SubscriberStatus::Complained,
+            )
+            .await?;
+        }
+        PostmarkEvent::Delivery(delivery) => {
+            let event = NewEmailEvent {
+                event_type: "delivery",
+                bounce_type: None,
+                email: &delivery.recipient,
+                message_id: delivery.message_id.as_deref(),
+                occurred_at: delivery.delivered_at,
+            };
+            insert_email_event(&mut transaction, &event, &payload).await?;
+        }
+        PostmarkEvent::Unsupported => {
+            tracing::info!("Ignoring an unsupported Postmark event.");
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record an email event.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Compares digests rather than the secrets themselves, so that the time
+/// taken does not tell how much of the password was right.
+fn authenticate(
+    request: &HttpRequest,
+    settings: &PostmarkWebhookSettings,
+) -> Result<(), anyhow::Error> {
+    let credentials = basic_authentication(request.headers())?;
+    let expected = Sha256::digest(
+        format!(
+            "{}:{}",
+            settings.username,
+            settings.password.expose_secret()
+        )
+        .as_bytes(),
+    );
+    let actual = Sha256::digest(
+        format!(
+            "{}:{}",
+            credentials.username,
+            credentials.password.expose_secret()
+        )
+        .as_bytes(),
+    );
+    if expected != actual {
+        anyhow::bail!("Invalid webhook credentials.");
+    }
+    Ok(())
+}
+
+struct NewEmailEvent<'a> {
+    event_type: &'static str,
+    bounce_type: Option<&'a str>,
+    email: &'a str,
+    message_id: Option<&'a str>,
+    occurred_at: DateTime<Utc>,
+}
+
+/// Returns `false` if the event was recorded already: Postmark sends a
+/// webhook again when it does not get an answer in time.
+#[tracing::instrument(name = "Record an email event", skip_all)]
+async fn insert_email_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &NewEmailEvent<'_>,
+    payload: &serde_json::Value,
+) -> Result<bool, anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            id, event_type, bounce_type, email, message_id, occurred_at, payload
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.event_type,
+        event.bounce_type,
+        event.email,
+        event.message_id,
+        event.occurred_at,
+        payload,
+    );
+    let inserted = transaction
+        .execute(query)
+        .await
+        .context("Failed to record the email event.")?
+        .rows_affected()
+        == 1;
+    if !inserted {
+        tracing::info!("Ignoring an email event that was recorded already.");
+    }
+    Ok(inserted)
+}
+
+/// Moves every membership of the address that allows it to `status`.
+/// Addresses are compared ignoring case, as they are stored as typed.
+#[tracing::instrument(name = "Mark memberships after an email event", skip(transaction))]
+async fn mark_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    status: SubscriberStatus,
+) -> Result<(), anyhow::Error> {
+    let memberships = sqlx::query!(
+        r#"
+        SELECT m.newsletter_id, m.subscriber_id, m.status
+        FROM newsletter_subscriptions m
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        WHERE lower(s.email) = lower($1)
+        FOR UPDATE OF m
+        "#,
+        email
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to look up the memberships of the address.")?;
+    for membership in memberships {
+        let current = SubscriberStatus::parse(&membership.status).map_err(anyhow::Error::msg)?;
+        if !current.can_transition_to(status) {
+            continue;
+        }
+        set_membership_status(
+            transaction,
+            membership.newsletter_id,
+            membership.subscriber_id,
+            current,
+            status,
+        )
+        .await
+        .context("Failed to update the membership status.")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,310 @@
+//! Segments narrow down who receives an issue among the confirmed members
+//! of its list.
+//!
+//! A definition is a tree of conditions stored as JSON, e.g.
+//! `{"all": [{"tag": "rust"}, {"not": {"email_domain": "example.com"}}]}`.
+//! It is compiled to a SQL condition on the `subscriptions s` and
+//! `newsletter_subscriptions m` rows, where every value is a bound
+//! parameter.
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+/// Keeps definitions small enough to be read by a human, and cheap to run.
+const MAX_CONDITIONS: usize = 50;
+const MAX_DEPTH: usize = 8;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Condition {
+    /// Matches when every condition matches, or always when empty.
+    All(Vec<Condition>),
+    /// Matches when any condition matches, or never when empty.
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    Tag(String),
+    /// Joined the list at or after the given time.
+    SubscribedAfter(DateTime<Utc>),
+    /// Joined the list before the given time.
+    SubscribedBefore(DateTime<Utc>),
+    /// The part of the address after the `@`, ignoring case.
+    EmailDomain(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentDefinition(Condition);
+
+impl SegmentDefinition {
+    pub fn parse(s: &str) -> Result<SegmentDefinition, String> {
+        let value = serde_json::from_str(s)
+            .map_err(|e| format!("The segment definition is not valid JSON: {}", e))?;
+        Self::from_value(value)
+    }
+
+    pub fn from_value(value: serde_json::Value) -> Result<SegmentDefinition, String> {
+        let condition: Condition = serde_json::from_value(value)
+            .map_err(|e| format!("The segment definition is invalid: {}", e))?;
+        let condition = validate(condition, 1, &mut 0)?;
+        Ok(Self(condition))
+    }
+
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(&self.0).expect("Conditions can always be serialized")
+    }
+
+    /// Appends the condition, wrapped in parentheses, to `query`.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        push_condition(&self.0, query);
+    }
+}
+
+impl AsRef<Condition> for SegmentDefinition {
+    fn as_ref(&self) -> &Condition {
+        &self.0
+    }
+}
+
+/// Tags are compared ignoring case and surrounding spaces.
+pub fn normalize_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err("Tags cannot be empty.".into());
+    }
+    if tag.chars().count() > 64 {
+        return Err(format!("The tag `{}` is longer than 64 characters.", tag));
+    }
+    Ok(tag)
+}
+
+fn validate(condition: Condition, depth: usize, count: &mut usize) -> Result<Condition, String> {
+    *count += 1;
+    if *count > MAX_CONDITIONS {
+        return Err(format!(
+            "A segment cannot have more than {} conditions.",
+            MAX_CONDITIONS
+        ));
+    }
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "Segment conditions cannot be nested more than {} levels deep.",
+            MAX_DEPTH
+        ));
+    }
+    let condition = match condition {
+        Condition::All(conditions) => Condition::All(
+            conditions
+                .into_iter()
+                .map(|c| validate(c, depth + 1, count))
+                .collect::<Result<_, _>>()?,
+        ),
+        Condition::Any(conditions) => Condition::Any(
+            conditions
+                .into_iter()
+                .map(|c| validate(c, depth + 1, count))
+                .collect::<Result<_, _>>()?,
+        ),
+        Condition::Not(condition) => {
+            Condition::Not(Box::new(validate(*condition, depth + 1, count)?))
+        }
+        Condition::Tag(tag) => Condition::Tag(normalize_tag(&tag)?),
+        Condition::EmailDomain(domain) => {
+            let domain = domain.trim().to_lowercase();
+            if domain.is_empty() || domain.contains('@') || domain.contains(char::is_whitespace) {
+                return Err(format!("`{}` is not a valid email domain.", domain));
+            }
+            Condition::EmailDomain(domain)
+        }
+        c @ (Condition::SubscribedAfter(_) | Condition::SubscribedBefore(_)) => c,
+    };
+    Ok(condition)
+}
+
+fn push_condition(condition: &Condition, query: &mut QueryBuilder<'_, Postgres>) {
+    match condition {
+        Condition::All(conditions) => push_list(conditions, " AND ", "TRUE", query),
+        Condition::Any(conditions) => push_list(conditions, " OR ", "FALSE", query),
+        Condition::Not(condition) => {
+            query.push("(NOT ");
+            push_condition(condition, query);
+            query.push(")");
+        }
+        Condition::Tag(tag) => {
+            query
+                .push(
+                    "(EXISTS (SELECT 1 FROM subscriber_tags t \
+                    WHERE t.subscriber_id = s.id AND t.tag = ",
+                )
+                .push_bind(tag.clone())
+                .push("))");
+        }
+        Condition::SubscribedAfter(after) => {
+            query
+                .push("(m.subscribed_at >= ")
+                .push_bind(*after)
+                .push(")");
+        }
+        Condition::SubscribedBefore(before) => {
+            query
+                .push("(m.subscribed_at < ")
+                .push_bind(*before)
+                .push(")");
+        }
+        Condition::EmailDomain(domain) => {
+            query
+                .push("(lower(split_part(s.email, '@', 2)) = ")
+                .push_bind(domain.clone())
+                .push(")");
+        }
+    }
+}
+
+fn push_list(
+    conditions: &[Condition],
+    separator: &str,
+    empty: &str,
+    query: &mut QueryBuilder<'_, Postgres>,
+) {
+    if conditions.is_empty() {
+        query.push(empty);
+        return;
+    }
+    query.push("(");
+    for (i, condition) in conditions.iter().enumerate() {
+        if i > 0 {
+            query.push(separator);
+        }
+        push_condition(condition, query);
+    }
+    query.push(")");
+}
+
+pub struct Segment {
+    pub segment_id: Uuid,
+    pub name: String,
+    pub definition: SegmentDefinition,
+}
+
+/// `None` if there is no such segment.
+#[tracing::instrument(name = "Get segment", skip(executor))]
+pub async fn get_segment(
+    executor: impl PgExecutor<'_>,
+    segment_id: Uuid,
+) -> Result<Option<Segment>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT segment_id, name, definition FROM segments WHERE segment_id = $1"#,
+        segment_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    row.map(|r| {
+        Ok(Segment {
+            segment_id: r.segment_id,
+            name: r.name,
+            definition: SegmentDefinition::from_value(r.definition).map_err(anyhow::Error::msg)?,
+        })
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition, SegmentDefinition};
+    use claims::{assert_err, assert_ok};
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn sql(definition: &str) -> String {
+        let definition = SegmentDefinition::parse(definition).unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("");
+        definition.push_sql(&mut query);
+        query.sql().to_string()
+    }
+
+    #[test]
+    fn conditions_are_compiled_with_bound_values() {
+        assert_eq!(
+            sql(r#"{"all": [{"tag": "rust"}, {"not": {"email_domain": "example.com"}}]}"#),
+            "((EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id \
+            AND t.tag = $1)) AND (NOT (lower(split_part(s.email, '@', 2)) = $2)))"
+        );
+        assert_eq!(
+            sql(r#"{"any": [
+                {"subscribed_after": "2025-01-01T00:00:00Z"},
+                {"subscribed_before": "2024-01-01T00:00:00Z"}
+            ]}"#),
+            "((m.subscribed_at >= $1) OR (m.subscribed_at < $2))"
+        );
+    }
+
+    #[test]
+    fn empty_lists_match_everybody_or_nobody() {
+        assert_eq!(sql(r#"{"all": []}"#), "TRUE");
+        assert_eq!(sql(r#"{"any": []}"#), "FALSE");
+    }
+
+    #[test]
+    fn values_never_end_up_in_the_sql() {
+        let sql = sql(r#"{"tag": "'); DROP TABLE subscriptions; --"}"#);
+        assert!(!sql.contains("DROP"));
+    }
+
+    #[test]
+    fn tags_and_domains_are_normalized() {
+        let definition = SegmentDefinition::parse(
+            r#"{"all": [{"tag": " Rust "}, {"email_domain": "Example.COM"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            definition.as_ref(),
+            &Condition::All(vec![
+                Condition::Tag("rust".into()),
+                Condition::EmailDomain("example.com".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn definitions_round_trip_through_json() {
+        let definition = SegmentDefinition::parse(r#"{"not": {"tag": "rust"}}"#).unwrap();
+        assert_eq!(
+            SegmentDefinition::from_value(definition.to_value()).unwrap(),
+            definition
+        );
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        for definition in [
+            "",
+            "{}",
+            r#"{"tag": ""}"#,
+            r#"{"tag": "rust", "email_domain": "example.com"}"#,
+            r#"{"email_domain": "ursula@example.com"}"#,
+            r#"{"subscribed_after": "yesterday"}"#,
+            r#"{"unknown": "value"}"#,
+            r#"{"all": {"tag": "rust"}}"#,
+        ] {
+            assert_err!(SegmentDefinition::parse(definition), "{}", definition);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_or_large_definitions_are_rejected() {
+        let mut nested = r#"{"tag": "rust"}"#.to_string();
+        for _ in 0..8 {
+            nested = format!(r#"{{"not": {}}}"#, nested);
+        }
+        assert_err!(SegmentDefinition::parse(&nested));
+
+        let tags = vec![r#"{"tag": "rust"}"#; 50].join(",");
+        assert_err!(SegmentDefinition::parse(&format!(
+            r#"{{"any": [{}]}}"#,
+            tags
+        )));
+        let tags = vec![r#"{"tag": "rust"}"#; 49].join(",");
+        assert_ok!(SegmentDefinition::parse(&format!(
+            r#"{{"any": [{}]}}"#,
+            tags
+        )));
+    }
+}
@@ -1,34 +1,62 @@
+use crate::authentication::{reject_anonymous_users, PgSessionStore};
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscribe, confirm, publish_newsletter, home};
+use crate::email_queue::{
+    run_email_worker_until_stopped, ConfirmationEmail, PersonalDataEmail,
+    UnsubscribeAcknowledgement, WelcomeEmail,
+};
+use crate::email_templates::{EmailTemplate, EmailTemplates};
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::routes::{
+    admin_dashboard, archive, archived_issue, cancel_issue, change_password,
+    change_password_form, confirm, create_draft, create_segment, delete_segment,
+    delete_subscriber, download_personal_data, edit_draft_form, erase_personal_data,
+    erasure_form, export_subscribers, health_check, home, import_subscribers, issue_details,
+    list_issues, list_segments, list_subscribers, log_out, login, login_form, new_draft_form,
+    postmark_webhook, preview_issue, publish_draft, publish_newsletter, request_personal_data,
+    resend_confirmation, send_test_email, subscribe, subscriber_details, tag_subscriber,
+    unsubscribe, unsubscribe_form, update_draft, update_subscriber,
+};
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
+use actix_web::middleware::from_fn;
 use actix_web::{{dev::Server},web, App, HttpServer};
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, {PgPool}};
+use std::future::Future;
 use std::net::TcpListener;
+use std::pin::Pin;
+use tokio::task::JoinSet;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    connection_pool: PgPool,
+    /// Used by the workers, which retry failed sends through their queue.
+    queue_email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    background_tasks: JoinSet<(&'static str, Result<(), anyhow::Error>)>,
 }
 
+type BackgroundTask = Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send>>;
+
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
 
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout
-        );
+        let email_client = configuration.email_client.clone().client();
+        let queue_email_client = configuration.email_client.clone().client_without_retries();
+        let mut email_templates = EmailTemplates::load(&configuration.email_templates)
+            .expect("Failed to load the email templates.");
+        if let Some(subject) = &configuration.welcome_email.subject {
+            email_templates
+                .set_subject(EmailTemplate::Welcome, subject)
+                .expect("Invalid welcome email subject.");
+        }
 
         let address = format!(
             "{}:{}",
@@ -37,23 +65,121 @@ impl Application {
         );
         
         let listener: TcpListener = TcpListener::bind(address)?;
+        let workers: [(&'static str, BackgroundTask); 5] = [
+            (
+                "issue scheduler",
+                Box::pin(run_scheduler_until_stopped(connection_pool.clone())),
+            ),
+            (
+                "confirmation email worker",
+                Box::pin(run_email_worker_until_stopped(
+                    connection_pool.clone(),
+                    ConfirmationEmail {
+                        email_client: queue_email_client.clone(),
+                        templates: email_templates.clone(),
+                        base_url: configuration.application.base_url.clone(),
+                    },
+                )),
+            ),
+            (
+                "welcome email worker",
+                Box::pin(run_email_worker_until_stopped(
+                    connection_pool.clone(),
+                    WelcomeEmail {
+                        email_client: queue_email_client.clone(),
+                        templates: email_templates.clone(),
+                        base_url: configuration.application.base_url.clone(),
+                        hmac_secret: configuration.application.hmac_secret.clone(),
+                    },
+                )),
+            ),
+            (
+                "personal data email worker",
+                Box::pin(run_email_worker_until_stopped(
+                    connection_pool.clone(),
+                    PersonalDataEmail {
+                        email_client: queue_email_client.clone(),
+                        templates: email_templates.clone(),
+                        base_url: configuration.application.base_url.clone(),
+                    },
+                )),
+            ),
+            (
+                "unsubscribe email worker",
+                Box::pin(run_email_worker_until_stopped(
+                    connection_pool.clone(),
+                    UnsubscribeAcknowledgement {
+                        email_client: queue_email_client.clone(),
+                        templates: email_templates.clone(),
+                    },
+                )),
+            ),
+        ];
+        let mut background_tasks = JoinSet::new();
+        for (name, worker) in workers {
+            background_tasks.spawn(async move { (name, worker.await) });
+        }
         let port = listener.local_addr().unwrap().port();
         let server: Server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             email_client,
-            configuration.application.base_url,
+            email_templates,
+            configuration.clone(),
         )?;
 
-        Ok(Self { port, server})
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            queue_email_client,
+            base_url: configuration.application.base_url,
+            hmac_secret: configuration.application.hmac_secret,
+            background_tasks,
+        })
     }
     
     pub fn port(&self) -> u16 {
         self.port
     }
     
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+    /// Runs the HTTP server together with the newsletter delivery worker
+    /// and watches over the scheduler and the email workers started by
+    /// `build`. Returns as soon as any of them stops.
+    pub async fn run_until_stopped(mut self) -> Result<(), std::io::Error> {
+        let worker = run_worker_until_stopped(
+            self.connection_pool,
+            self.queue_email_client,
+            self.base_url,
+            self.hmac_secret,
+        );
+        tokio::select! {
+            outcome = self.server => outcome,
+            outcome = worker => {
+                tracing::error!(
+                    error.cause_chain = ?outcome,
+                    "The delivery worker stopped unexpectedly"
+                );
+                outcome.map_err(std::io::Error::other)
+            }
+            Some(outcome) = self.background_tasks.join_next() => match outcome {
+                Ok((name, outcome)) => {
+                    tracing::error!(
+                        error.cause_chain = ?outcome,
+                        "The {} stopped unexpectedly",
+                        name
+                    );
+                    outcome.map_err(std::io::Error::other)
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        "A background task stopped unexpectedly"
+                    );
+                    Err(std::io::Error::other(e))
+                }
+            },
+        }
     }
 }
 
@@ -65,26 +191,119 @@ pub fn get_connection_pool(
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// How long a subscription confirmation token can be used for.
+pub struct ConfirmationTokenTtl(pub std::time::Duration);
+
+/// Key used to sign session cookies and unsubscribe links.
+pub struct HmacSecret(pub Secret<String>);
+
 pub fn run(
     listener: TcpListener,
     pg_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
+    email_templates: EmailTemplates,
+    configuration: Settings,
 ) -> Result<Server, std::io::Error> {
+    let settings = configuration.application;
+    let secret_key = Key::try_from(settings.hmac_secret.expose_secret().as_bytes())
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "`application.hmac_secret` must be at least 64 bytes long.",
+            )
+        })?;
+    let public_archive = settings.public_archive;
+    let session_pool = pg_pool.clone();
     let db_pool = web::Data::new(pg_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_templates = web::Data::new(email_templates);
+    let confirmation_token_ttl =
+        web::Data::new(ConfirmationTokenTtl(settings.confirmation_token_ttl()));
+    let base_url = web::Data::new(ApplicationBaseUrl(settings.base_url));
+    let password_settings = web::Data::new(configuration.password);
+    let hmac_secret = web::Data::new(HmacSecret(settings.hmac_secret));
+    let welcome_email = web::Data::new(configuration.welcome_email);
+    let postmark_webhook_settings = web::Data::new(configuration.postmark_webhook);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(SessionMiddleware::new(
+                PgSessionStore::new(session_pool.clone()),
+                secret_key.clone(),
+            ))
             .wrap(TracingLogger :: default())
             .route("/health_check", web::get().to(health_check))
             .route("/", web::get().to(home))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/issues", web::get().to(list_issues))
+                    .route("/issues", web::post().to(create_draft))
+                    .route("/issues/new", web::get().to(new_draft_form))
+                    .route("/issues/{issue_id}", web::get().to(issue_details))
+                    .route("/issues/{issue_id}", web::post().to(update_draft))
+                    .route("/issues/{issue_id}/edit", web::get().to(edit_draft_form))
+                    .route("/issues/{issue_id}/preview", web::get().to(preview_issue))
+                    .route("/issues/{issue_id}/test", web::post().to(send_test_email))
+                    .route("/issues/{issue_id}/publish", web::post().to(publish_draft))
+                    .route("/issues/{issue_id}/cancel", web::post().to(cancel_issue))
+                    .route("/segments", web::get().to(list_segments))
+                    .route("/segments", web::post().to(create_segment))
+                    .route("/segments/{segment_id}/delete", web::post().to(delete_segment))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/subscribers/import", web::post().to(import_subscribers))
+                    .route("/subscribers/tags", web::post().to(tag_subscriber))
+                    .route("/subscribers/{subscriber_id}", web::get().to(subscriber_details))
+                    .route("/subscribers/{subscriber_id}", web::patch().to(update_subscriber))
+                    .route("/subscribers/{subscriber_id}", web::delete().to(delete_subscriber))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+            )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/resend-confirmation",
+                web::post().to(resend_confirmation),
+            )
+            .route("/subscriptions/unsubscribe", web::get().to(unsubscribe_form))
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/personal-data",
+                web::post().to(request_personal_data),
+            )
+            .route(
+                "/subscriptions/personal-data",
+                web::get().to(download_personal_data),
+            )
+            .route(
+                "/subscriptions/personal-data/erase",
+                web::get().to(erasure_form),
+            )
+            .route(
+                "/subscriptions/personal-data/erase",
+                web::post().to(erase_personal_data),
+            )
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
+            .configure(|cfg| {
+                if public_archive {
+                    cfg.route("/archive", web::get().to(archive))
+                        .route("/archive/{issue_id}", web::get().to(archived_issue));
+                }
+            })
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_templates.clone())
             .app_data(base_url.clone())
+            .app_data(password_settings.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(confirmation_token_ttl.clone())
+            .app_data(welcome_email.clone())
+            .app_data(postmark_webhook_settings.clone())
     })
     .listen(listener)?
     .run();
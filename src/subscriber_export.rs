@@ -0,0 +1,164 @@
+//! Encoding of subscriber exports, one row at a time.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    /// What comes before the first row.
+    pub fn header(&self) -> &'static str {
+        match self {
+            Self::Csv => "id,email,name,status,subscribed_at,confirmed_at\r\n",
+            Self::Ndjson => "",
+        }
+    }
+
+    /// Appends `row` to `output`.
+    pub fn encode(&self, row: &ExportRow, output: &mut Vec<u8>) {
+        match self {
+            Self::Csv => {
+                let fields = [
+                    row.id.to_string(),
+                    csv_field(&row.email),
+                    csv_field(&row.name),
+                    row.status.clone(),
+                    timestamp(&row.subscribed_at),
+                    row.confirmed_at.as_ref().map(timestamp).unwrap_or_default(),
+                ];
+                output.extend_from_slice(fields.join(",").as_bytes());
+                output.extend_from_slice(b"\r\n");
+            }
+            Self::Ndjson => {
+                serde_json::to_writer(&mut *output, row)
+                    .expect("Export rows can always be serialized");
+                output.push(b'\n');
+            }
+        }
+    }
+}
+
+/// A membership of the exported list.
+#[derive(serde::Serialize, Debug)]
+pub struct ExportRow {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+fn timestamp(t: &DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Quotes the field if it would otherwise be read as several, as RFC 4180
+/// asks. Fields that a spreadsheet would run as a formula are prefixed with
+/// a `'`, as subscribers choose their own name.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportFormat, ExportRow};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn row(name: &str) -> ExportRow {
+        ExportRow {
+            id: Uuid::nil(),
+            email: "ursula@example.com".into(),
+            name: name.into(),
+            status: "confirmed".into(),
+            subscribed_at: Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap(),
+            confirmed_at: None,
+        }
+    }
+
+    fn encode(format: ExportFormat, row: &ExportRow) -> String {
+        let mut output = vec![];
+        format.encode(row, &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_rows_follow_the_header() {
+        assert_eq!(
+            ExportFormat::Csv.header(),
+            "id,email,name,status,subscribed_at,confirmed_at\r\n"
+        );
+        assert_eq!(
+            encode(ExportFormat::Csv, &row("Ursula")),
+            "00000000-0000-0000-0000-000000000000,ursula@example.com,Ursula,confirmed,\
+            2025-01-02T03:04:05.000000Z,\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert!(encode(ExportFormat::Csv, &row("Le Guin, \"Ursula\"\nK."))
+            .contains(",\"Le Guin, \"\"Ursula\"\"\nK.\","));
+    }
+
+    #[test]
+    fn csv_fields_that_look_like_formulas_are_neutralized() {
+        for (name, field) in [
+            (
+                "=HYPERLINK(\"http://example.com\")",
+                "\"'=HYPERLINK(\"\"http://example.com\"\")\"",
+            ),
+            ("+1", "'+1"),
+            ("-1", "'-1"),
+            ("@SUM(A1)", "'@SUM(A1)"),
+            ("\tUrsula", "'\tUrsula"),
+            ("\rUrsula", "\"'\rUrsula\""),
+        ] {
+            assert!(
+                encode(ExportFormat::Csv, &row(name)).contains(&format!(",{},", field)),
+                "{:?} was not neutralized",
+                name
+            );
+        }
+        assert!(encode(ExportFormat::Csv, &row("Ursula-K.")).contains(",Ursula-K.,"));
+    }
+
+    #[test]
+    fn ndjson_rows_are_one_object_per_line() {
+        let line = encode(ExportFormat::Ndjson, &row("Ursula\nK."));
+        assert_eq!(line.matches('\n').count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["name"], "Ursula\nK.");
+        assert_eq!(value["confirmed_at"], serde_json::Value::Null);
+    }
+}
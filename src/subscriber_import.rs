@@ -0,0 +1,398 @@
+//! Decoding of bulk subscriber uploads, one chunk at a time.
+//!
+//! Uploads are either CSV, with a header naming at least the `email` and
+//! `name` columns, or JSON lines of `{"email": ..., "name": ...}` objects.
+//! Both may have a `locale`. Every row is validated on its own: a bad row
+//! is reported and skipped, it does not fail the upload.
+
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberLocale, SubscriberName};
+use csv_core::ReadRecordResult;
+
+/// Protects memory from uploads without line breaks.
+const MAX_ROW_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    JsonLines,
+}
+
+impl ImportFormat {
+    /// `None` if the content type is neither CSV nor JSON lines.
+    pub fn from_content_type(content_type: &str) -> Option<ImportFormat> {
+        let mime = content_type.split(';').next()?.trim().to_lowercase();
+        match mime.as_str() {
+            "text/csv" => Some(Self::Csv),
+            "application/x-ndjson" | "application/jsonl" | "application/x-jsonlines" => {
+                Some(Self::JsonLines)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A row that failed validation, by the line it starts on.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct RowError {
+    pub line: u64,
+    pub error: String,
+}
+
+pub type ImportedRow = Result<NewSubscriber, RowError>;
+
+pub enum RowDecoder {
+    Csv(Box<CsvDecoder>),
+    JsonLines(JsonLinesDecoder),
+}
+
+impl RowDecoder {
+    pub fn new(format: ImportFormat) -> RowDecoder {
+        match format {
+            ImportFormat::Csv => Self::Csv(Box::default()),
+            ImportFormat::JsonLines => Self::JsonLines(JsonLinesDecoder::default()),
+        }
+    }
+
+    /// Returns the rows completed by `chunk`.
+    /// Fails if the upload as a whole cannot be read any further.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<ImportedRow>, String> {
+        // `csv_core` takes empty input as the end of the upload.
+        if chunk.is_empty() {
+            return Ok(vec![]);
+        }
+        match self {
+            Self::Csv(decoder) => decoder.decode(chunk),
+            Self::JsonLines(decoder) => decoder.decode(chunk),
+        }
+    }
+
+    /// Returns the last row, once the whole upload has been fed.
+    pub fn finish(&mut self) -> Result<Vec<ImportedRow>, String> {
+        match self {
+            Self::Csv(decoder) => decoder.decode(&[]),
+            Self::JsonLines(decoder) => decoder.finish(),
+        }
+    }
+}
+
+fn validate(line: u64, email: String, name: String, locale: Option<String>) -> ImportedRow {
+    let row = || -> Result<NewSubscriber, String> {
+        Ok(NewSubscriber {
+            email: SubscriberEmail::parse(email.trim().to_string())?,
+            name: SubscriberName::parse(name)?,
+            locale: locale
+                .filter(|locale| !locale.trim().is_empty())
+                .map(SubscriberLocale::parse)
+                .transpose()?,
+        })
+    };
+    row().map_err(|error| RowError { line, error })
+}
+
+/// Where the columns we read are in the header.
+struct Columns {
+    email: usize,
+    name: usize,
+    locale: Option<usize>,
+}
+
+impl Columns {
+    fn parse(header: &[String]) -> Result<Columns, String> {
+        let position = |column: &str| {
+            header.iter().position(|h| {
+                h.trim_start_matches('\u{feff}')
+                    .trim()
+                    .eq_ignore_ascii_case(column)
+            })
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Columns {
+                email,
+                name,
+                locale: position("locale"),
+            }),
+            _ => Err("The CSV header must have an `email` and a `name` column.".into()),
+        }
+    }
+}
+
+/// Feeds `csv_core`, which keeps the state of a record split across chunks.
+pub struct CsvDecoder {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    record_line: u64,
+    columns: Option<Columns>,
+}
+
+impl Default for CsvDecoder {
+    fn default() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+            record_line: 1,
+            columns: None,
+        }
+    }
+}
+
+impl CsvDecoder {
+    /// An empty `input` marks the end of the upload.
+    fn decode(&mut self, mut input: &[u8]) -> Result<Vec<ImportedRow>, String> {
+        let mut rows = vec![];
+        loop {
+            let (result, n_in, n_out, n_ends) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[n_in..];
+            self.output_len += n_out;
+            self.ends_len += n_ends;
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return Ok(rows),
+                ReadRecordResult::OutputFull => {
+                    if self.output.len() >= MAX_ROW_LENGTH {
+                        return Err(format!(
+                            "The row on line {} is longer than {} bytes.",
+                            self.record_line, MAX_ROW_LENGTH
+                        ));
+                    }
+                    self.output.resize(self.output.len() * 2, 0);
+                }
+                ReadRecordResult::OutputEndsFull => {
+                    if self.ends.len() >= MAX_ROW_LENGTH {
+                        return Err(format!(
+                            "The row on line {} has too many columns.",
+                            self.record_line
+                        ));
+                    }
+                    self.ends.resize(self.ends.len() * 2, 0);
+                }
+                ReadRecordResult::Record => {
+                    if let Some(row) = self.take_record()? {
+                        rows.push(row);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `None` for the header.
+    fn take_record(&mut self) -> Result<Option<ImportedRow>, String> {
+        let line = self.record_line;
+        self.record_line = self.reader.line();
+        let mut fields = Vec::with_capacity(self.ends_len);
+        let mut start = 0;
+        for &end in &self.ends[..self.ends_len] {
+            fields.push(String::from_utf8(self.output[start..end].to_vec()));
+            start = end;
+        }
+        self.output_len = 0;
+        self.ends_len = 0;
+
+        let Some(columns) = &self.columns else {
+            let header = fields
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| "The CSV header is not valid UTF-8.".to_string())?;
+            self.columns = Some(Columns::parse(&header)?);
+            return Ok(None);
+        };
+        let mut field = |i: usize| -> Result<String, RowError> {
+            match fields.get_mut(i) {
+                Some(Ok(value)) => Ok(std::mem::take(value)),
+                Some(Err(_)) => Err(RowError {
+                    line,
+                    error: "The row is not valid UTF-8.".into(),
+                }),
+                None => Ok(String::new()),
+            }
+        };
+        let row = (|| {
+            let email = field(columns.email)?;
+            let name = field(columns.name)?;
+            let locale = columns.locale.map(&mut field).transpose()?;
+            validate(line, email, name, locale)
+        })();
+        Ok(Some(row))
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRow {
+    email: String,
+    name: String,
+    locale: Option<String>,
+}
+
+#[derive(Default)]
+pub struct JsonLinesDecoder {
+    /// The start of a line whose end has not been received yet.
+    pending: Vec<u8>,
+    line: u64,
+}
+
+impl JsonLinesDecoder {
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<ImportedRow>, String> {
+        self.pending.extend_from_slice(chunk);
+        let mut rows = vec![];
+        let mut start = 0;
+        while let Some(end) = self.pending[start..].iter().position(|&b| b == b'\n') {
+            let line = self.pending[start..start + end].to_vec();
+            start += end + 1;
+            rows.extend(self.decode_line(&line));
+        }
+        self.pending.drain(..start);
+        if self.pending.len() > MAX_ROW_LENGTH {
+            return Err(format!(
+                "Line {} is longer than {} bytes.",
+                self.line + 1,
+                MAX_ROW_LENGTH
+            ));
+        }
+        Ok(rows)
+    }
+
+    fn finish(&mut self) -> Result<Vec<ImportedRow>, String> {
+        let line = std::mem::take(&mut self.pending);
+        Ok(self.decode_line(&line).into_iter().collect())
+    }
+
+    /// `None` for blank lines.
+    fn decode_line(&mut self, line: &[u8]) -> Option<ImportedRow> {
+        self.line += 1;
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return None;
+        }
+        let row = match serde_json::from_slice::<JsonRow>(line) {
+            Ok(row) => validate(self.line, row.email, row.name, row.locale),
+            Err(e) => Err(RowError {
+                line: self.line,
+                error: format!("The row is not a valid subscriber: {}", e),
+            }),
+        };
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportFormat, ImportedRow, RowDecoder};
+    use claims::{assert_err, assert_none};
+
+    /// Feeds `upload` `chunk_size` bytes at a time.
+    fn decode(format: ImportFormat, upload: &str, chunk_size: usize) -> Vec<ImportedRow> {
+        let mut decoder = RowDecoder::new(format);
+        let mut rows = vec![];
+        for chunk in upload.as_bytes().chunks(chunk_size) {
+            rows.extend(decoder.feed(chunk).unwrap());
+        }
+        rows.extend(decoder.finish().unwrap());
+        rows
+    }
+
+    fn summary(rows: &[ImportedRow]) -> Vec<Result<String, u64>> {
+        rows.iter()
+            .map(|row| match row {
+                Ok(subscriber) => Ok(subscriber.email.as_ref().to_string()),
+                Err(e) => Err(e.line),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn csv_rows_are_read_whatever_the_chunk_boundaries() {
+        let upload = "\u{feff}Name,Email,Locale\n\
+            Ursula,ursula@example.com,fr\n\
+            \"Le Guin, Ursula\",\"le.guin@example.com\",\n\
+            Ferris,not-an-email,\n\
+            Graydon,graydon@example.com";
+
+        for chunk_size in [1, 3, 7, 1024] {
+            let rows = decode(ImportFormat::Csv, upload, chunk_size);
+            assert_eq!(
+                summary(&rows),
+                vec![
+                    Ok("ursula@example.com".into()),
+                    Ok("le.guin@example.com".into()),
+                    Err(4),
+                    Ok("graydon@example.com".into()),
+                ],
+                "chunk size {}",
+                chunk_size
+            );
+            let subscriber = rows[1].as_ref().unwrap();
+            assert_eq!(subscriber.name.as_ref(), "Le Guin, Ursula");
+            assert_none!(&subscriber.locale);
+            assert_eq!(
+                rows[0].as_ref().unwrap().locale.as_ref().unwrap().as_ref(),
+                "fr"
+            );
+        }
+    }
+
+    #[test]
+    fn csv_uploads_need_email_and_name_columns() {
+        let mut decoder = RowDecoder::new(ImportFormat::Csv);
+        assert_err!(decoder.feed(b"email,first_name\nursula@example.com,Ursula\n"));
+    }
+
+    #[test]
+    fn short_csv_rows_are_rejected() {
+        let rows = decode(ImportFormat::Csv, "email,name\nursula@example.com\n", 1024);
+        assert_eq!(summary(&rows), vec![Err(2)]);
+    }
+
+    #[test]
+    fn json_lines_are_read_whatever_the_chunk_boundaries() {
+        let upload = "{\"email\": \"ursula@example.com\", \"name\": \"Ursula\"}\r\n\
+            \n\
+            {\"email\": \"ferris@example.com\"}\n\
+            not json\n\
+            {\"email\": \"graydon@example.com\", \"name\": \"Graydon\", \"locale\": \"pt_BR\"}";
+
+        for chunk_size in [1, 5, 1024] {
+            let rows = decode(ImportFormat::JsonLines, upload, chunk_size);
+            assert_eq!(
+                summary(&rows),
+                vec![
+                    Ok("ursula@example.com".into()),
+                    Err(3),
+                    Err(4),
+                    Ok("graydon@example.com".into()),
+                ],
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn rows_without_line_breaks_are_bounded() {
+        let mut decoder = RowDecoder::new(ImportFormat::JsonLines);
+        assert_err!(decoder.feed(&vec![b'a'; 65 * 1024]));
+        let mut decoder = RowDecoder::new(ImportFormat::Csv);
+        assert_err!(decoder.feed(format!("email,name\n{}", "a".repeat(65 * 1024)).as_bytes()));
+    }
+
+    #[test]
+    fn content_types_are_recognized() {
+        assert_eq!(
+            ImportFormat::from_content_type("text/csv; charset=utf-8"),
+            Some(ImportFormat::Csv)
+        );
+        assert_eq!(
+            ImportFormat::from_content_type("application/x-ndjson"),
+            Some(ImportFormat::JsonLines)
+        );
+        assert_eq!(ImportFormat::from_content_type("application/json"), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Placeholders in issues and transactional emails, filled in for each
+//! recipient.
+//!
+//! The syntax is deliberately tiny: `{{ variable }}` where `variable` is one
+//! of those allowed where the template is used. There are no loops,
+//! conditionals or filters, so rendering cannot fail once a template has
+//! been parsed.
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("The placeholder opened at character {0} is never closed.")]
+    UnclosedPlaceholder(usize),
+    #[error("`{{{{{0}}}}}` is not a known placeholder.")]
+    UnknownVariable(String),
+}
+
+/// Provides the values substituted for each placeholder.
+pub trait Context {
+    fn value(&self, variable: &str) -> &str;
+}
+
+/// Values as name/value pairs, missing ones render as blanks.
+impl Context for [(&str, &str)] {
+    fn value(&self, variable: &str) -> &str {
+        self.iter()
+            .find(|(name, _)| *name == variable)
+            .map(|(_, value)| *value)
+            .unwrap_or_default()
+    }
+}
+
+/// The values available in newsletter issues.
+pub struct IssueContext<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+}
+
+impl IssueContext<'_> {
+    pub const VARIABLES: &'static [&'static str] = &["name", "email", "unsubscribe_url"];
+}
+
+impl Context for IssueContext<'_> {
+    fn value(&self, variable: &str) -> &str {
+        match variable {
+            "name" => self.name,
+            "email" => self.email,
+            "unsubscribe_url" => self.unsubscribe_url,
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Segment<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+#[derive(Debug)]
+pub struct Template<'a> {
+    segments: Vec<Segment<'a>>,
+}
+
+impl<'a> Template<'a> {
+    /// Rejects placeholders for anything but `variables`.
+    pub fn parse(s: &'a str, variables: &[&str]) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Literal(&rest[..start]));
+            }
+            let after_opening = &rest[start + 2..];
+            let end = after_opening.find("}}").ok_or_else(|| {
+                let offset = s.len() - rest.len() + start;
+                TemplateError::UnclosedPlaceholder(s[..offset].chars().count() + 1)
+            })?;
+            let name = after_opening[..end].trim();
+            if !variables.contains(&name) {
+                return Err(TemplateError::UnknownVariable(name.to_string()));
+            }
+            segments.push(Segment::Placeholder(name));
+            rest = &after_opening[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn uses(&self, variable: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder(v) if *v == variable))
+    }
+
+    pub fn render_text(&self, context: &(impl Context + ?Sized)) -> String {
+        self.render(context, |value| value.to_string())
+    }
+
+    /// Substituted values are escaped, the template itself is trusted.
+    pub fn render_html(&self, context: &(impl Context + ?Sized)) -> String {
+        self.render(context, escape_html)
+    }
+
+    fn render(
+        &self,
+        context: &(impl Context + ?Sized),
+        escape: impl Fn(&str) -> String,
+    ) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => output.push_str(literal),
+                Segment::Placeholder(variable) => {
+                    output.push_str(&escape(context.value(variable)))
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Escapes the characters that are significant in element content as well
+/// as in quoted attribute values, leaving URLs otherwise readable.
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IssueContext, Template, TemplateError};
+    use claims::{assert_err, assert_ok};
+
+    fn context() -> IssueContext<'static> {
+        IssueContext {
+            name: "Ursula \"<Le Guin>\"",
+            email: "ursula@example.com",
+            unsubscribe_url: "https://example.com/unsubscribe?a=1&b=2",
+        }
+    }
+
+    #[test]
+    fn text_without_placeholders_is_rendered_verbatim() {
+        let template = Template::parse("Hello { there }", IssueContext::VARIABLES).unwrap();
+        assert_eq!(template.render_text(&context()), "Hello { there }");
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let template =
+            Template::parse("Hi {{name}}, ({{ email }})", IssueContext::VARIABLES).unwrap();
+        assert_eq!(
+            template.render_text(&context()),
+            "Hi Ursula \"<Le Guin>\", (ursula@example.com)"
+        );
+    }
+
+    #[test]
+    fn substituted_values_are_escaped_in_html() {
+        let template = Template::parse(
+            r#"<p>Hi {{name}}</p><a href="{{unsubscribe_url}}">x</a>"#,
+            IssueContext::VARIABLES,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render_html(&context()),
+            r#"<p>Hi Ursula &quot;&lt;Le Guin&gt;&quot;</p><a href="https://example.com/unsubscribe?a=1&amp;b=2">x</a>"#
+        );
+    }
+
+    #[test]
+    fn used_variables_are_reported() {
+        let template = Template::parse("{{unsubscribe_url}}", IssueContext::VARIABLES).unwrap();
+        assert!(template.uses("unsubscribe_url"));
+        assert!(!template.uses("name"));
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert_eq!(
+            Template::parse("Hi {{name", IssueContext::VARIABLES).err(),
+            Some(TemplateError::UnclosedPlaceholder(4))
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_eq!(
+            Template::parse("Hi {{ surname }}", IssueContext::VARIABLES).err(),
+            Some(TemplateError::UnknownVariable("surname".into()))
+        );
+        assert_err!(Template::parse("Hi {{}}", IssueContext::VARIABLES));
+    }
+
+    #[test]
+    fn placeholders_are_looked_up_in_name_value_pairs() {
+        let template = Template::parse("Visit {{ link }}", &["link"]).unwrap();
+        let values: &[(&str, &str)] = &[("link", "https://example.com")];
+        assert_eq!(template.render_text(values), "Visit https://example.com");
+    }
+
+    #[test]
+    fn every_known_placeholder_is_accepted() {
+        assert_ok!(Template::parse(
+            "{{name}}{{email}}{{unsubscribe_url}}",
+            IssueContext::VARIABLES
+        ));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_issue(app: &TestApp, title: &str) -> String {
+    let response = app
+        .post_newsletters(json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+    .to_string()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_issue_history() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_issues().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_with_their_delivery_stats() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app, "An <important> issue").await;
+    app.dispatch_all_emails_until_given_up().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_admin_issues().await.text().await.unwrap();
+    assert!(html_page.contains(&format!(r#"href="/admin/issues/{}""#, issue_id)));
+    assert!(html_page.contains("An &lt;important&gt; issue"));
+    assert!(html_page.contains(&app.test_user.username));
+    assert!(html_page.contains("1 delivered, 1 failed, 0 skipped, 0 pending"));
+
+    let response = app.get_admin_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p>Newsletter body as HTML</p>"));
+    assert!(html_page.contains("1 delivered, 1 failed, 0 skipped, 0 pending"));
+}
+
+#[tokio::test]
+async fn an_unknown_issue_returns_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_admin_issue(&uuid::Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,327 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Subscribes `email` to the default list and returns the subscriber id.
+async fn subscribe(app: &TestApp, email: &str) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!(
+        "name=le%20guin&email={}",
+        email.replace('@', "%40")
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_confirmation_emails().await;
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the subscriber.")
+        .id
+        .to_string()
+}
+
+async fn confirm(app: &TestApp, email: &str) {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .rev()
+        .find(|r| serde_json::from_slice::<Value>(&r.body).unwrap()["To"].as_str() == Some(email))
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn emails(response: reqwest::Response) -> (Vec<String>, Option<String>) {
+    assert_eq!(response.status().as_u16(), 200);
+    let page: Value = response.json().await.unwrap();
+    let emails = page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_string())
+        .collect();
+    (emails, page["next_cursor"].as_str().map(str::to_string))
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4().to_string();
+
+    let responses = [
+        app.get_admin_subscribers(&[]).await,
+        app.get_admin_subscriber(&subscriber_id).await,
+        app.patch_admin_subscriber(&subscriber_id, &json!({"name": "Ursula"}))
+            .await,
+        app.delete_admin_subscriber(&subscriber_id).await,
+    ];
+
+    for response in responses {
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_newest_first_one_page_at_a_time() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        subscribe(&app, email).await;
+    }
+
+    let (first_page, cursor) = emails(app.get_admin_subscribers(&[("limit", "2")]).await).await;
+    let cursor = cursor.expect("A second page was expected.");
+    let (second_page, last_cursor) = emails(
+        app.get_admin_subscribers(&[("limit", "2"), ("cursor", &cursor)])
+            .await,
+    )
+    .await;
+
+    assert_eq!(first_page, vec!["c@example.com", "b@example.com"]);
+    assert_eq!(second_page, vec!["a@example.com"]);
+    assert_eq!(last_cursor, None);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    subscribe(&app, "ursula@example.com").await;
+    subscribe(&app, "ferris@rust.dev").await;
+    subscribe(&app, "graydon@rust.dev").await;
+    confirm(&app, "ferris@rust.dev").await;
+
+    let (confirmed, _) = emails(app.get_admin_subscribers(&[("status", "confirmed")]).await).await;
+    let (rust, _) = emails(app.get_admin_subscribers(&[("email", "RUST.dev")]).await).await;
+    let (pending_rust, _) = emails(
+        app.get_admin_subscribers(&[("status", "pending_confirmation"), ("email", "rust")])
+            .await,
+    )
+    .await;
+    let (wildcard, _) = emails(app.get_admin_subscribers(&[("email", "%")]).await).await;
+
+    assert_eq!(confirmed, vec!["ferris@rust.dev"]);
+    assert_eq!(rust, vec!["graydon@rust.dev", "ferris@rust.dev"]);
+    assert_eq!(pending_rust, vec!["graydon@rust.dev"]);
+    assert!(wildcard.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_list_queries_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = [
+        ("status", "gone", "unknown status"),
+        ("limit", "0", "limit too small"),
+        ("limit", "1000", "limit too large"),
+        ("cursor", "garbage", "malformed cursor"),
+    ];
+
+    for (key, value, description) in test_cases {
+        let response = app.get_admin_subscribers(&[(key, value)]).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with a 400 when the query had an {}.",
+            description
+        );
+        let body: Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn subscriber_details_include_memberships_and_tags() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscribe(&app, "ursula@example.com").await;
+    app.post_subscriber_tag(
+        &json!({"email": "ursula@example.com", "tag": "rust", "action": "add"}),
+    )
+    .await;
+
+    let response = app.get_admin_subscriber(&subscriber_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: Value = response.json().await.unwrap();
+    assert_eq!(subscriber["id"], subscriber_id);
+    assert_eq!(subscriber["email"], "ursula@example.com");
+    assert_eq!(subscriber["name"], "le guin");
+    assert_eq!(subscriber["tags"], json!(["rust"]));
+    assert_eq!(subscriber["memberships"][0]["list_name"], "Newsletter");
+    assert_eq!(
+        subscriber["memberships"][0]["status"],
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = Uuid::new_v4().to_string();
+
+    let responses = [
+        app.get_admin_subscriber(&subscriber_id).await,
+        app.patch_admin_subscriber(&subscriber_id, &json!({"name": "Ursula"}))
+            .await,
+        app.delete_admin_subscriber(&subscriber_id).await,
+    ];
+
+    for response in responses {
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn subscriber_details_can_be_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscribe(&app, "ursula@example.com").await;
+
+    let response = app
+        .patch_admin_subscriber(
+            &subscriber_id,
+            &json!({"email": "ursula@earthsea.org", "name": "Ursula", "locale": "FR"}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: Value = response.json().await.unwrap();
+    assert_eq!(subscriber["email"], "ursula@earthsea.org");
+    assert_eq!(subscriber["name"], "Ursula");
+    assert_eq!(subscriber["locale"], "fr");
+}
+
+#[tokio::test]
+async fn invalid_edits_are_rejected_and_change_nothing() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscribe(&app, "ursula@example.com").await;
+    subscribe(&app, "ferris@rust.dev").await;
+    let test_cases = [
+        (json!({"email": "not-an-email", "name": "Ursula"}), 400),
+        (json!({"name": "", "locale": "fr"}), 400),
+        (json!({"locale": "not a locale"}), 400),
+        (json!({"status": "gone"}), 400),
+        (json!({"country": "fr"}), 400),
+        (
+            json!({"status": "confirmed", "list_id": Uuid::new_v4()}),
+            400,
+        ),
+        (json!({"email": "ferris@rust.dev", "name": "Ursula"}), 409),
+    ];
+
+    for (body, status) in test_cases {
+        let response = app.patch_admin_subscriber(&subscriber_id, &body).await;
+
+        assert_eq!(response.status().as_u16(), status, "{}", body);
+    }
+    let saved = sqlx::query!(
+        "SELECT email, name, locale FROM subscriptions WHERE id = $1",
+        Uuid::parse_str(&subscriber_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.email, "ursula@example.com");
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.locale, None);
+}
+
+#[tokio::test]
+async fn statuses_can_be_changed_along_allowed_transitions() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscribe(&app, "ursula@example.com").await;
+
+    let confirmed = app
+        .patch_admin_subscriber(&subscriber_id, &json!({"status": "confirmed"}))
+        .await;
+    let unsubscribed = app
+        .patch_admin_subscriber(&subscriber_id, &json!({"status": "unsubscribed"}))
+        .await;
+    let back_to_confirmed = app
+        .patch_admin_subscriber(&subscriber_id, &json!({"status": "confirmed"}))
+        .await;
+
+    assert_eq!(confirmed.status().as_u16(), 200);
+    let subscriber: Value = confirmed.json().await.unwrap();
+    assert_eq!(subscriber["address_confirmed"], true);
+    assert_eq!(subscriber["memberships"][0]["status"], "confirmed");
+    assert_eq!(unsubscribed.status().as_u16(), 200);
+    assert_eq!(back_to_confirmed.status().as_u16(), 409);
+    let body: Value = back_to_confirmed.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("unsubscribed"));
+}
+
+#[tokio::test]
+async fn changing_the_status_of_a_list_the_subscriber_is_not_on_is_a_409() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscribe(&app, "ursula@example.com").await;
+    let list_id = app.create_newsletter_list("Weekly digest").await;
+
+    let response = app
+        .patch_admin_subscriber(
+            &subscriber_id,
+            &json!({"status": "confirmed", "list_id": list_id}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn deleted_subscribers_are_gone_with_their_memberships_and_tags() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscribe(&app, "ursula@example.com").await;
+    app.post_subscriber_tag(
+        &json!({"email": "ursula@example.com", "tag": "rust", "action": "add"}),
+    )
+    .await;
+
+    let response = app.delete_admin_subscriber(&subscriber_id).await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(
+        app.get_admin_subscriber(&subscriber_id)
+            .await
+            .status()
+            .as_u16(),
+        404
+    );
+    let leftovers = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM newsletter_subscriptions) AS "memberships!",
+            (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT COUNT(*) FROM subscriber_tags) AS "tags!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        (leftovers.memberships, leftovers.tokens, leftovers.tags),
+        (0, 0, 0)
+    );
+    // The address can subscribe again from scratch.
+    subscribe(&app, "ursula@example.com").await;
+}
@@ -0,0 +1,47 @@
+use crate::helpers::spawn_app;
+use serde_json::json;
+
+#[tokio::test]
+async fn the_archive_lists_and_renders_past_issues() {
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(json!({
+            "title": "Issue #1",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    let archive = reqwest::get(&format!("{}/archive", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(archive.contains(&format!(r#"<a href="/archive/{}">Issue #1</a>"#, issue_id)));
+
+    let issue = reqwest::get(&format!("{}/archive/{}", app.address, issue_id))
+        .await
+        .unwrap();
+    assert_eq!(issue.status().as_u16(), 200);
+    assert!(issue.text().await.unwrap().contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn an_unknown_archived_issue_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!("{}/archive/{}", app.address, uuid::Uuid::new_v4()))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
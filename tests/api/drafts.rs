@@ -0,0 +1,241 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn draft_body(title: &str) -> serde_json::Value {
+    json!({
+        "title": title,
+        "text_content": "Draft body as plain text",
+        "html_content": "<p>Draft body as HTML</p>",
+    })
+}
+
+/// Creates a draft as the logged-in user and returns its id.
+async fn create_draft(app: &TestApp) -> String {
+    let response = app.post_draft(&draft_body("Draft title")).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    location.strip_prefix("/admin/issues/").unwrap().to_string()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_create_a_draft() {
+    let app = spawn_app().await;
+
+    let response = app.post_draft(&draft_body("Draft title")).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_are_saved_without_being_sent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+
+    let issue_id = create_draft(&app).await;
+
+    let html_page = app.get_admin_issue(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains("Draft"));
+    let html_page = app.get_admin_issues().await.text().await.unwrap();
+    assert!(html_page.contains("Draft title"));
+    app.dispatch_all_pending_emails().await;
+    let archive = reqwest::get(&format!("{}/archive", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!archive.contains("Draft title"));
+}
+
+#[tokio::test]
+async fn drafts_with_an_empty_title_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_draft(&draft_body(" ")).await;
+
+    assert_is_redirect_to(&response, "/admin/issues/new");
+    let count = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn unknown_issue_statuses_are_rejected_by_the_database() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_draft(&app).await;
+
+    let result = sqlx::query!("UPDATE newsletter_issues SET status = 'Published'")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn edited_drafts_are_shown_in_the_preview() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    let response = app
+        .post_edit_draft(
+            &issue_id,
+            &json!({
+                "title": "Edited title",
+                "text_content": "Use <b> for bold",
+                "html_content": "<p>Edited body</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+
+    let response = app.get_issue_preview(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Edited title"));
+    assert!(html_page.contains("<p>Edited body</p>"));
+    assert!(html_page.contains("Use &lt;b&gt; for bold"));
+}
+
+#[tokio::test]
+async fn a_test_email_is_sent_to_the_given_address_only() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_edit_draft(
+        &issue_id,
+        &json!({
+            "title": "Draft for {{name}}",
+            "text_content": "Sent to {{email}}",
+            "html_content": "<p>Sent to {{email}}</p>",
+        }),
+    )
+    .await;
+
+    let response = app.post_send_test(&issue_id, "editor@example.com").await;
+
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_admin_issue(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("A test email has been sent to editor@example.com."));
+    let received_requests = app.email_server.received_requests().await.unwrap();
+    let email_request = received_requests.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[Test] Draft for Test Subscriber");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Sent to editor@example.com\n\nUnsubscribe: "));
+    // The link is signed like a subscriber's, and works without
+    // unsubscribing anybody.
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query!("SELECT status FROM newsletter_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_preview_is_personalized_like_a_delivery() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.post_edit_draft(
+        &issue_id,
+        &json!({
+            "title": "Draft for {{name}}",
+            "text_content": "Sent to {{email}}",
+            "html_content": "<p>Sent to {{email}}</p>",
+        }),
+    )
+    .await;
+
+    let response = app.get_issue_preview(&issue_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(!html_page.contains("{{"));
+    assert!(html_page.contains("Draft for Test Subscriber"));
+    assert!(html_page.contains("<p>Sent to subscriber@example.com</p>"));
+    assert!(html_page.contains("Unsubscribe: http://127.0.0.1/subscriptions/unsubscribe?token="));
+    assert!(html_page.contains(r#"<a href="http://127.0.0.1/subscriptions/unsubscribe?token="#));
+}
+
+#[tokio::test]
+async fn a_test_email_to_an_invalid_address_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_send_test(&issue_id, "not-an-email").await;
+
+    let html_page = app.get_admin_issue(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("not-an-email is not a valid subscriber email."));
+}
+
+#[tokio::test]
+async fn a_published_draft_is_delivered_once_and_can_no_longer_be_edited() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_publish_draft(&issue_id).await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_admin_issue(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("The issue has been published."));
+
+    app.post_publish_draft(&issue_id).await;
+    let html_page = app.get_admin_issue(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("Only drafts can be published."));
+
+    app.post_edit_draft(&issue_id, &draft_body("Edited title")).await;
+    let html_page = app.get_admin_issue(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("Only drafts can be edited."));
+    app.dispatch_all_pending_emails().await;
+}
@@ -3,12 +3,23 @@ use argon2::password_hash::SaltString;
 use linkify::LinkKind;
 use once_cell::sync::Lazy;
 use reqwest::Url;
-use secrecy::ExposeSecret;
-use sha3::Digest;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::future::Future;
 use uuid::Uuid;
-use wiremock::{MockServer, Request};
-use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, PostmarkWebhookSettings, Settings,
+};
+use zero2prod::email_client::EmailClient;
+use zero2prod::email_queue::{
+    try_send_queued_email, ConfirmationEmail, EmailJob, PersonalDataEmail, UnsubscribeAcknowledgement,
+    WelcomeEmail,
+};
+use zero2prod::email_templates::{EmailTemplate, EmailTemplates};
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::try_publish_due_issue;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -45,13 +56,19 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
-    pub test_user: TestUser
+    pub test_user: TestUser,
+    pub email_client: EmailClient,
+    pub api_client: reqwest::Client,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub email_templates: EmailTemplates,
+    pub postmark_webhook: PostmarkWebhookSettings,
 }
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -59,52 +76,545 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
     
+    pub async fn create_newsletter_list(&self, name: &str) -> Uuid {
+        let list_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO newsletters (newsletter_id, name) VALUES ($1, $2)",
+            list_id,
+            name
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to create a newsletter list.");
+        list_id
+    }
+
+    pub async fn post_resend_confirmation(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/resend-confirmation", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_personal_data_request(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/personal-data", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(
         &self, 
         body: serde_json::Value
+    ) -> reqwest::Response {
+        self.post_newsletters_with_idempotency_key(
+            body,
+            &Uuid::new_v4().to_string()
+        )
+        .await
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str
     ) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
     
-    pub async fn test_user(&self) -> (String, String) {
-        let row = sqlx::query!("SELECT username, password_hash FROM users LIMIT 1")
-            .fetch_one(&self.db_pool)
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.postmark_webhook.username,
+                Some(self.postmark_webhook.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_admin_issues(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issue(&self, issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues/{}", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_cancel_issue(&self, issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/issues/{}/cancel", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("/admin/issues", body).await
+    }
+
+    pub async fn post_edit_draft<Body>(&self, issue_id: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form(&format!("/admin/issues/{}", issue_id), body)
+            .await
+    }
+
+    pub async fn get_issue_preview(&self, issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues/{}/preview", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_send_test(&self, issue_id: &str, email: &str) -> reqwest::Response {
+        self.post_admin_form(
+            &format!("/admin/issues/{}/test", issue_id),
+            &serde_json::json!({ "email": email }),
+        )
+        .await
+    }
+
+    pub async fn post_publish_draft(&self, issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/issues/{}/publish", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_segments_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/segments", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_segment<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("/admin/segments", body).await
+    }
+
+    pub async fn post_delete_segment(&self, segment_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/segments/{}/delete", &self.address, segment_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriber_tag<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("/admin/subscribers/tags", body).await
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers", &self.address))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscriber(&self, subscriber_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/{}", &self.address, subscriber_id))
+            .send()
             .await
-            .expect("Failed to create test users");
-        (row.username, row.password_hash)
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn patch_admin_subscriber(
+        &self,
+        subscriber_id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .patch(format!("{}/admin/subscribers/{}", &self.address, subscriber_id))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_admin_subscriber(&self, subscriber_id: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/subscribers/{}", &self.address, subscriber_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriber_import(
+        &self,
+        content_type: &str,
+        query: &[(&str, &str)],
+        body: String,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .query(query)
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber_export(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/export", &self.address))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn post_admin_form<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Runs `try_fn` until `queue` holds no task that is due, waiting for
+    /// tasks currently held by the background workers as well.
+    async fn drain_queue<F, Fut>(&self, queue: &str, try_fn: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<ExecutionOutcome, anyhow::Error>>,
+    {
+        let remaining = format!("SELECT COUNT(*) FROM {} WHERE execute_after <= now()", queue);
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_fn().await.unwrap() {
+                let remaining: i64 = sqlx::query_scalar(&remaining)
+                    .fetch_one(&self.db_pool)
+                    .await
+                    .unwrap();
+                if remaining == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+    }
+
+    /// Drains the delivery queue. Failed deliveries waiting for a retry are
+    /// left in the queue.
+    pub async fn dispatch_all_pending_emails(&self) {
+        self.drain_queue("issue_delivery_queue", || {
+            try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+        })
+        .await
+    }
+
+    /// Retries failed deliveries straight away, instead of backing off,
+    /// until they either succeed or run out of attempts.
+    pub async fn dispatch_all_emails_until_given_up(&self) {
+        loop {
+            self.dispatch_all_pending_emails().await;
+            let retried = sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+                .execute(&self.db_pool)
+                .await
+                .unwrap()
+                .rows_affected();
+            if retried == 0 {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_confirmation_emails(&self) {
+        let job = ConfirmationEmail {
+            email_client: self.email_client.clone(),
+            templates: self.email_templates.clone(),
+            base_url: self.base_url.clone(),
+        };
+        self.drain_email_queue(job).await
+    }
+
+    pub async fn dispatch_welcome_emails(&self) {
+        let job = WelcomeEmail {
+            email_client: self.email_client.clone(),
+            templates: self.email_templates.clone(),
+            base_url: self.base_url.clone(),
+            hmac_secret: self.hmac_secret.clone(),
+        };
+        self.drain_email_queue(job).await
+    }
+
+    pub async fn dispatch_personal_data_emails(&self) {
+        let job = PersonalDataEmail {
+            email_client: self.email_client.clone(),
+            templates: self.email_templates.clone(),
+            base_url: self.base_url.clone(),
+        };
+        self.drain_email_queue(job).await
+    }
+
+    pub async fn dispatch_unsubscribe_emails(&self) {
+        let job = UnsubscribeAcknowledgement {
+            email_client: self.email_client.clone(),
+            templates: self.email_templates.clone(),
+        };
+        self.drain_email_queue(job).await
+    }
+
+    /// Sends every email of `job`'s kind that is due.
+    async fn drain_email_queue<J: EmailJob>(&self, job: J) {
+        self.drain_queue(J::QUEUE, || try_send_queued_email(&self.db_pool, &job))
+            .await
+    }
+
+    /// Enqueues every scheduled issue that is due, waiting for those
+    /// currently held by the background scheduler as well.
+    pub async fn publish_due_issues(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_publish_due_issue(&self.db_pool).await.unwrap()
+            {
+                let remaining = sqlx::query!(
+                    r#"
+                    SELECT COUNT(*) AS "count!" FROM newsletter_issues
+                    WHERE status = 'scheduled' AND send_at <= now()
+                    "#
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap()
+                .count;
+                if remaining == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
     }
 
     pub fn get_confirmation_links(&self, email_request: &Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
-        let get_link = |s: &str| {
-            let links: Vec<_> = linkify::LinkFinder::new()
-                .links(s)
-                .filter(|l| *l.kind() == LinkKind::Url)
-                .collect();
-            assert_eq!(links.len(), 1);
-            let raw_link = links[0].as_str().to_owned();
-            let mut confirmation_link: Url = Url::parse(&raw_link).unwrap();
-
-            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
-            confirmation_link.set_port(Some(self.port)).unwrap();
-            confirmation_link
-        };
-        
-        let html: Url = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text: Url = get_link(&body["TextBody"].as_str().unwrap());
+        let html: Url = self.get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text: Url = self.get_link(body["TextBody"].as_str().unwrap());
         
         ConfirmationLinks {
             html,
             plain_text
         }
     }
+
+    /// Extracts the unsubscribe link from a newsletter issue, checking that
+    /// the HTML body, the plain text body and the `List-Unsubscribe` header
+    /// all agree on it.
+    pub fn get_unsubscribe_link(&self, email_request: &Request) -> Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let html = self.get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = self.get_link(body["TextBody"].as_str().unwrap());
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .unwrap()["Value"]
+            .as_str()
+            .unwrap();
+        let header = self.get_link(header.trim_start_matches('<').trim_end_matches('>'));
+
+        assert_eq!(html, plain_text);
+        assert_eq!(html, header);
+        html
+    }
+
+    /// Extracts the download and erasure links, in this order, from a
+    /// personal data email.
+    pub fn get_personal_data_links(&self, email_request: &Request) -> (Url, Url) {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let links = self.get_links(body["TextBody"].as_str().unwrap());
+        assert_eq!(links.len(), 2);
+        assert_eq!(links, self.get_links(body["HtmlBody"].as_str().unwrap()));
+        (links[0].clone(), links[1].clone())
+    }
+
+    /// Returns the only link in `s`, pointed at the test server.
+    fn get_link(&self, s: &str) -> Url {
+        let mut links = self.get_links(s);
+        assert_eq!(links.len(), 1);
+        links.pop().unwrap()
+    }
+
+    fn get_links(&self, s: &str) -> Vec<Url> {
+        linkify::LinkFinder::new()
+            .links(s)
+            .filter(|l| *l.kind() == LinkKind::Url)
+            .map(|l| {
+                let mut link: Url = Url::parse(l.as_str()).unwrap();
+                assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+                link.set_port(Some(self.port)).unwrap();
+                link
+            })
+            .collect()
+    }
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+     let body = format!(
+         "name=le%20guin&email={}%40gmail.com",
+         Uuid::new_v4()
+     );
+     
+     let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+     
+     app.post_subscriptions(body)
+         .await
+         .error_for_status()
+         .unwrap();
+     app.dispatch_confirmation_emails().await;
+        
+     let email_request = &app
+         .email_server
+         .received_requests()
+         .await
+         .unwrap()
+         .pop()
+         .unwrap();
+        
+     app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
 pub struct TestUser {
@@ -114,6 +624,14 @@ pub struct TestUser {
 }
 
 impl TestUser {
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
+
     pub fn generate() -> Self {
         Self {
             user_id: Uuid::new_v4(),
@@ -148,6 +666,12 @@ impl TestUser {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Welcome emails are disabled unless `configure` turns them back on, so
+/// that they do not reach the mock email server behind the tests' backs.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server: MockServer = MockServer::start().await;
@@ -156,7 +680,9 @@ pub async fn spawn_app() -> TestApp {
         let mut c: Settings      = get_configuration().expect("failed to read configuration");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port       = 0;
-        c.email_client.base_url  = email_server.uri();
+        c.email_client.postmark.base_url = email_server.uri();
+        c.welcome_email.enabled  = false;
+        configure(&mut c);
         c
     };
 
@@ -166,7 +692,20 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("failed to build application");
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    drop(tokio::spawn(application.run_until_stopped()));
+
+    let mut email_templates = EmailTemplates::load(&configuration.email_templates).unwrap();
+    if let Some(subject) = &configuration.welcome_email.subject {
+        email_templates
+            .set_subject(EmailTemplate::Welcome, subject)
+            .unwrap();
+    }
+
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
     let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
@@ -174,6 +713,12 @@ pub async fn spawn_app() -> TestApp {
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         test_user: TestUser::generate(),
+        email_client: configuration.email_client.clone().client_without_retries(),
+        api_client,
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        email_templates,
+        postmark_webhook: configuration.postmark_webhook.clone(),
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -191,7 +736,7 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .expect("Failed to create database");
 
     let connection_pool = PgPool::connect(
-        &config.connection_string().expose_secret()
+        config.connection_string().expose_secret()
     )
         .await
         .expect("Failed to connect to Postgres");
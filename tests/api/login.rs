@@ -0,0 +1,78 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn sessions_are_persisted_in_the_database() {
+    let app = spawn_app().await;
+
+    app.test_user.login(&app).await;
+
+    let n_sessions = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM sessions WHERE user_id = $1"#,
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_sessions, 1);
+}
+
+#[tokio::test]
+async fn expired_sessions_are_deleted_when_a_new_one_is_saved() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (session_key, user_id, state, expires_at)
+        VALUES ('expired', $1, '{}', now() - interval '1 minute')
+        "#,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    app.test_user.login(&app).await;
+
+    let session_keys: Vec<String> = sqlx::query!("SELECT session_key FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.session_key)
+        .collect();
+    assert_eq!(session_keys.len(), 1);
+    assert_ne!(session_keys[0], "expired");
+}
@@ -2,4 +2,19 @@ mod helpers;
 mod health_check;
 mod subscriptions;
 mod subscriptions_confirm;
-mod newsletter;
\ No newline at end of file
+mod newsletter;
+mod login;
+mod admin_dashboard;
+mod change_password;
+mod unsubscribe;
+mod resend_confirmation;
+mod admin_issues;
+mod drafts;
+mod archive;
+mod scheduled_publishing;
+mod webhooks;
+mod segments;
+mod admin_subscribers;
+mod subscriber_import;
+mod subscriber_export;
+mod personal_data;
@@ -1,4 +1,6 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
 use serde_json::{json, Value};
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
@@ -12,7 +14,7 @@ async fn non_existing_user_is_rejected(){
     let password = Uuid::new_v4().to_string();
     
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", app.address))
+        .post(format!("{}/newsletters", app.address))
         .basic_auth(username, Some(password))
         .json(&json!({ 
             "title": "Newsletter title" ,
@@ -43,7 +45,7 @@ async fn invalid_password_is_rejected(){
     assert_ne!(app.test_user.password, password);
     
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", app.address))
+        .post(format!("{}/newsletters", app.address))
         .basic_auth(username, Some(password))
         .json(&json!({ 
             "title": "Newsletter title" ,
@@ -69,7 +71,7 @@ async fn requests_missing_authorization_are_rejected(){
     let app = spawn_app().await;
     
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&json!({
             "title": "Newsletter title",
             "content": {
@@ -106,7 +108,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers(){
     
     let response = app.post_newsletters(newsletter_request_body).await;
     
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -131,7 +134,238 @@ async fn newsletters_are_delivered_to_confirmed_subscribers(){
 
     let response = app.post_newsletters(newsletter_request_body).await;
     
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_members_of_their_list(){
+    let app: TestApp = spawn_app().await;
+    let list_id = app.create_newsletter_list("Rust weekly").await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let member = sqlx::query!(
+        r#"
+        UPDATE newsletter_subscriptions SET newsletter_id = $1
+        WHERE subscriber_id = (SELECT id FROM subscriptions LIMIT 1)
+        RETURNING subscriber_id
+        "#,
+        list_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let member_email = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        member.subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .email;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "list_id": list_id,
+    })).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[2];
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], member_email);
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected_with_a_400(){
+    let app: TestApp = spawn_app().await;
+
+    let response = app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "list_id": Uuid::new_v4(),
+    })).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_reason_for_a_400_is_returned_in_the_body(){
+    let app: TestApp = spawn_app().await;
+
+    let response = app.post_newsletters(json!({
+        "title": "a".repeat(201),
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+    })).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "The title of an issue cannot be longer than 200 characters."
+    );
+}
+
+#[tokio::test]
+async fn publishing_returns_before_the_issue_is_delivered(){
+    let app: TestApp = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let queued = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(queued, 2);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_failed_delivery_does_not_stop_the_rest_of_the_issue(){
+    let app: TestApp = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_failed_delivery_is_retried_later() {
+    let app: TestApp = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() AS "deferred!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.deferred);
+    let deliveries = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(deliveries, 0);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_emails_until_given_up().await;
+    let outcome = sqlx::query!("SELECT outcome FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .outcome;
+    assert_eq!(outcome, "delivered");
+}
+
+#[tokio::test]
+async fn each_delivery_attempt_sends_a_single_request() {
+    let app: TestApp = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // Retryable for the email client, which leaves it to the queue.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let n_retries = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n_retries;
+    assert_eq!(n_retries, 1);
 }
 
 #[tokio::test]
@@ -150,6 +384,55 @@ async fn newsletters_returns_400_for_invalid_data(){
         (
            serde_json::json!({"title": "Newsletter"}),
             "missing content"
+        ),
+        (
+            serde_json::json!({
+                "title": " ",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>"
+                }
+            }),
+            "empty title"
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": ""
+                }
+            }),
+            "empty HTML content"
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter",
+                "content": {
+                    "html": format!("<p>{}</p>", "a".repeat(100_001))
+                }
+            }),
+            "content too long"
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter",
+                "content": {
+                    "text": "Hi {{ name",
+                    "html": "<p>Hi {{ name }}</p>"
+                }
+            }),
+            "unclosed placeholder"
+        ),
+        (
+            serde_json::json!({
+                "title": "News for {{nickname}}",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>"
+                }
+            }),
+            "unknown placeholder"
         )
     ];
     
@@ -167,38 +450,195 @@ async fn newsletters_returns_400_for_invalid_data(){
     
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-     let body: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-     
-     let _mock_guard = Mock::given(path("/email"))
-            .and(method("POST"))
-            .respond_with(ResponseTemplate::new(200))
-            .named("Create unconfirmed subscriber")
-            .expect(1)
-            .mount_as_scoped(&app.email_server)
-            .await;
-     
-     app.post_subscriptions(body.into())
-         .await
-         .error_for_status()
-         .unwrap();
-        
-     let email_request = &app
-         .email_server
-         .received_requests()
-         .await
-         .unwrap()
-         .pop()
-         .unwrap();
-        
-     app.get_confirmation_links(&email_request)
+#[tokio::test]
+async fn subscribers_receive_both_the_html_and_the_text_content() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let received_requests = app.email_server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&received_requests.last().unwrap().body).unwrap();
+    assert!(body["HtmlBody"].as_str().unwrap().starts_with("<p>Newsletter body as HTML</p>"));
+    assert!(body["TextBody"].as_str().unwrap().starts_with("Newsletter body as plain text"));
+}
+
+#[tokio::test]
+async fn the_text_content_is_generated_from_the_html_when_missing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<h1>Hello</h1><p>Newsletter body as <b>HTML</b></p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let received_requests = app.email_server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&received_requests.last().unwrap().body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.contains("Hello"));
+    assert!(text_body.contains("Newsletter body as"));
+    assert!(!text_body.contains("<p>"));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[tokio::test]
+async fn issues_are_personalized_for_each_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(json!({
+        "title": "News for {{name}}",
+        "content": {
+            "text": "Hi {{ name }} ({{email}}), leave at {{unsubscribe_url}}",
+            "html": r#"<p>Hi {{name}}</p><a href="{{unsubscribe_url}}">Leave</a>"#,
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let received_requests = app.email_server.received_requests().await.unwrap();
+    let email_request = received_requests.last().unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    let email = body["To"].as_str().unwrap();
+    assert_eq!(body["Subject"], "News for le guin");
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with(&format!("Hi le guin ({}), leave at ", email)));
+    assert!(body["HtmlBody"].as_str().unwrap().starts_with("<p>Hi le guin</p>"));
+    // Bodies placing the unsubscribe link themselves do not get a second one.
+    app.get_unsubscribe_link(email_request);
+    assert!(!text_body.contains("Unsubscribe:"));
+    assert!(!body["HtmlBody"].as_str().unwrap().contains("Unsubscribe"));
+}
+
+#[tokio::test]
+async fn requests_missing_an_idempotency_key_are_rejected(){
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
         .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
\ No newline at end of file
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent(){
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_newsletters_with_idempotency_key(
+            newsletter_request_body.clone(),
+            &idempotency_key
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let response = app
+        .post_newsletters_with_idempotency_key(
+            newsletter_request_body,
+            &idempotency_key
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully(){
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response1 = app.post_newsletters_with_idempotency_key(
+        newsletter_request_body.clone(),
+        &idempotency_key
+    );
+    let response2 = app.post_newsletters_with_idempotency_key(
+        newsletter_request_body,
+        &idempotency_key
+    );
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+}
@@ -0,0 +1,347 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use reqwest::Url;
+use serde_json::{json, Value};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+/// Sends an issue to the confirmed subscriber and records its delivery
+/// through the webhook, so that they have a history.
+async fn send_issue(app: &TestApp, email: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let response = app
+        .post_postmark_webhook(&json!({
+            "RecordType": "Delivery",
+            "MessageStream": "outbound",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": email,
+            "DeliveredAt": "2025-09-01T16:33:54Z",
+            "Details": "Test delivery webhook details"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+/// Returns the download and erasure links emailed to `email`.
+async fn request_personal_data(app: &TestApp, email: &str) -> (Url, Url) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_personal_data_request(format!("email={}", email.replace('@', "%40")))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_personal_data_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_personal_data_links(&email_request)
+}
+
+async fn count(app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_response_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_personal_data_request("email=ursula%40example.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_personal_data_emails().await;
+    assert_eq!(count(&app, "personal_data_email_queue").await, 0);
+    assert_eq!(count(&app, "personal_data_tokens").await, 0);
+}
+
+#[tokio::test]
+async fn addresses_that_differ_only_in_case_are_kept_apart() {
+    let app = spawn_app().await;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    for body in [
+        "name=le%20guin&email=ursula%40example.com",
+        "name=Le%20Guin&email=Ursula%40example.com",
+    ] {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    app.dispatch_confirmation_emails().await;
+    drop(mock_guard);
+
+    let (data_link, _) = request_personal_data(&app, "Ursula@example.com").await;
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let email: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["To"], "Ursula@example.com");
+    let other_case = app
+        .post_personal_data_request("email=URSULA%40example.com".into())
+        .await;
+
+    assert_eq!(other_case.status().as_u16(), 200);
+    app.dispatch_personal_data_emails().await;
+    assert_eq!(count(&app, "personal_data_tokens").await, 1);
+    let data: Value = reqwest::get(data_link).await.unwrap().json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "Ursula@example.com");
+    assert_eq!(data["subscriber"]["name"], "Le Guin");
+}
+
+#[tokio::test]
+async fn a_failed_personal_data_email_is_retried_later() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_personal_data_request(format!("email={}", email.replace('@', "%40")))
+        .await;
+    app.dispatch_personal_data_emails().await;
+
+    let task = sqlx::query!("SELECT n_retries FROM personal_data_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+    // No link was sent, so none can be used.
+    assert_eq!(count(&app, "personal_data_tokens").await, 0);
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_personal_data_request("email=not-an-email".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_data_link_returns_everything_we_hold_about_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    send_issue(&app, &email).await;
+    let (data_link, _) = request_personal_data(&app, &email).await;
+
+    let response = reqwest::get(data_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"personal-data.json\""
+    );
+    let data: Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], email.as_str());
+    assert_eq!(data["subscriber"]["name"], "le guin");
+    assert_eq!(data["memberships"][0]["status"], "confirmed");
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert!(data["subscription_tokens"][0]["consumed_at"].is_string());
+    assert_eq!(data["deliveries"][0]["title"], "Newsletter title");
+    assert_eq!(data["deliveries"][0]["outcome"], "delivered");
+    assert_eq!(data["email_events"][0]["event_type"], "delivery");
+}
+
+#[tokio::test]
+async fn unknown_and_expired_links_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let (mut data_link, erasure_link) = request_personal_data(&app, &email).await;
+    let token = data_link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    data_link.set_query(Some("token=unknown"));
+
+    let unknown = reqwest::get(data_link).await.unwrap();
+    sqlx::query!("UPDATE personal_data_tokens SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let expired = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(unknown.status().as_u16(), 401);
+    assert_eq!(expired.status().as_u16(), 410);
+    assert_eq!(count(&app, "subscriptions").await, 1);
+    // Only a hash of the token is stored.
+    let stored = sqlx::query!("SELECT token_hash FROM personal_data_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_hash;
+    assert_ne!(stored, token);
+}
+
+#[tokio::test]
+async fn the_erasure_page_does_not_erase_on_its_own() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let (_, erasure_link) = request_personal_data(&app, &email).await;
+
+    let response = reqwest::get(erasure_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(r#"method="post""#));
+    assert_eq!(count(&app, "subscriptions").await, 1);
+}
+
+#[tokio::test]
+async fn erasure_removes_the_subscriber_and_keeps_an_anonymous_record() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    send_issue(&app, &email).await;
+    let (data_link, erasure_link) = request_personal_data(&app, &email).await;
+
+    let response = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    for table in [
+        "subscriptions",
+        "newsletter_subscriptions",
+        "subscription_tokens",
+        "personal_data_tokens",
+        "email_events",
+    ] {
+        assert_eq!(count(&app, table).await, 0, "{} was not emptied", table);
+    }
+    let delivery = sqlx::query!("SELECT subscriber_email, outcome FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(delivery.subscriber_email, email);
+    assert_eq!(delivery.outcome, "delivered");
+    let erasure = sqlx::query!(
+        r#"
+        SELECT erasure_id, requested_by, admin_id, memberships,
+            anonymized_deliveries, deleted_email_events
+        FROM subscriber_erasures
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        delivery.subscriber_email,
+        format!("erased-{}", erasure.erasure_id)
+    );
+    assert_eq!(erasure.requested_by, "subscriber");
+    assert_eq!(erasure.admin_id, None);
+    assert_eq!(erasure.memberships, 1);
+    assert_eq!(erasure.anonymized_deliveries, 1);
+    assert_eq!(erasure.deleted_email_events, 1);
+    // The links stop working once the data is gone.
+    let response = reqwest::get(data_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn email_events_reported_in_another_case_are_returned_and_erased() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    send_issue(&app, &email.to_uppercase()).await;
+    let (data_link, erasure_link) = request_personal_data(&app, &email).await;
+
+    let data: Value = reqwest::get(data_link).await.unwrap().json().await.unwrap();
+    let response = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(data["email_events"][0]["event_type"], "delivery");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count(&app, "email_events").await, 0);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_through_the_admin_api_is_audited() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .delete_admin_subscriber(&subscriber_id.to_string())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    let erasure = sqlx::query!(
+        r#"
+        SELECT e.requested_by, u.username
+        FROM subscriber_erasures e
+        JOIN users u ON u.user_id = e.admin_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(erasure.requested_by, "admin");
+    assert_eq!(erasure.username, app.test_user.username);
+}
@@ -0,0 +1,106 @@
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn a_pending_subscriber_receives_a_new_working_link() {
+    let app = spawn_app().await;
+    let first_links = create_unconfirmed_subscriber(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_resend_confirmation(format!("email={}", email.replace('@', "%40")))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_confirmation_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(new_links.html, first_links.html);
+    let response = reqwest::get(new_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn nothing_is_sent_to_unknown_or_confirmed_addresses() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let confirmed_email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for email in [confirmed_email.as_str(), "nobody@example.com"] {
+        let response = app
+            .post_resend_confirmation(format!("email={}", email.replace('@', "%40")))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    app.dispatch_confirmation_emails().await;
+}
+
+#[tokio::test]
+async fn a_new_link_keeps_the_requested_locale() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_confirmation_emails().await;
+    let last_subject = || async {
+        let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+        let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        email["Subject"].as_str().unwrap().to_owned()
+    };
+
+    // Resent while the email asked for by the signup is still queued...
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr".into())
+        .await;
+    app.post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_confirmation_emails().await;
+    let queued = last_subject().await;
+    // ...and once it has been sent.
+    app.post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_confirmation_emails().await;
+    let sent = last_subject().await;
+
+    assert_eq!(queued, "Confirmez votre inscription à Newsletter");
+    assert_eq!(sent, "Confirmez votre inscription à Newsletter");
+}
+
+#[tokio::test]
+async fn resend_confirmation_returns_a_400_for_an_invalid_email() {
+    let app = spawn_app().await;
+
+    let response = app.post_resend_confirmation("email=definitely-not-an-email".into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
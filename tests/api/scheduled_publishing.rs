@@ -0,0 +1,154 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn schedule_issue(app: &TestApp, send_at: chrono::DateTime<chrono::Utc>) -> Value {
+    let response = app
+        .post_newsletters(json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "send_at": send_at,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    response.json().await.unwrap()
+}
+
+async fn make_all_issues_due(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn issue_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_is_only_delivered_once_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+    assert_eq!(body["status"], "scheduled");
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app).await, "scheduled");
+
+    make_all_issues_due(&app).await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app).await, "published");
+}
+
+#[tokio::test]
+async fn an_issue_scheduled_a_few_seconds_ago_is_published_straight_away() {
+    let app = spawn_app().await;
+
+    let body = schedule_issue(&app, chrono::Utc::now() - chrono::Duration::seconds(5)).await;
+
+    assert_eq!(body["status"], "published");
+    assert_eq!(issue_status(&app).await, "published");
+}
+
+#[tokio::test]
+async fn an_issue_scheduled_within_the_clock_skew_tolerance_is_published_straight_away() {
+    let app = spawn_app().await;
+
+    let body = schedule_issue(&app, chrono::Utc::now() - chrono::Duration::seconds(50)).await;
+
+    assert_eq!(body["status"], "published");
+    assert_eq!(issue_status(&app).await, "published");
+}
+
+#[tokio::test]
+async fn an_issue_scheduled_in_the_past_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let post_issue = |send_at: chrono::DateTime<chrono::Utc>| {
+        app.post_newsletters(json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "send_at": send_at,
+        }))
+    };
+
+    let just_beyond_tolerance = post_issue(chrono::Utc::now() - chrono::Duration::seconds(70)).await;
+    let long_ago = post_issue(chrono::Utc::now() - chrono::Duration::hours(1)).await;
+
+    for response in [just_beyond_tolerance, long_ago] {
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "send_at cannot be more than 60 seconds in the past."
+        );
+    }
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_listed_and_can_be_cancelled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let body = schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_admin_issues().await.text().await.unwrap();
+    assert!(html_page.contains("Scheduled for"));
+    assert!(html_page.contains(&format!(r#"action="/admin/issues/{}/cancel""#, issue_id)));
+
+    let response = app.post_cancel_issue(issue_id).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_admin_issues().await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The issue has been cancelled.</i></p>"));
+
+    make_all_issues_due(&app).await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app).await, "cancelled");
+}
+
+#[tokio::test]
+async fn a_published_issue_cannot_be_cancelled() {
+    let app = spawn_app().await;
+    let body = schedule_issue(&app, chrono::Utc::now() - chrono::Duration::seconds(5)).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_cancel_issue(body["newsletter_issue_id"].as_str().unwrap())
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_admin_issues().await.text().await.unwrap();
+    assert!(html_page.contains("cannot be cancelled"));
+    assert_eq!(issue_status(&app).await, "published");
+}
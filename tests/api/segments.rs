@@ -0,0 +1,262 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=le%20guin&email={}", email.replace('@', "%40")))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_confirmation_emails().await;
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn tag(app: &TestApp, email: &str, tag: &str) {
+    let response = app
+        .post_subscriber_tag(&json!({"email": email, "tag": tag, "action": "add"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+}
+
+/// Saves a segment as the logged-in user and returns its id.
+async fn create_segment(app: &TestApp, name: &str, definition: Value) -> String {
+    let response = app
+        .post_segment(&json!({"name": name, "definition": definition.to_string()}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+    sqlx::query!("SELECT segment_id FROM segments WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The segment was not saved.")
+        .segment_id
+        .to_string()
+}
+
+fn newsletter_body(segment_id: &str) -> Value {
+    json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment_id": segment_id,
+    })
+}
+
+async fn recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice::<Value>(&r.body).unwrap())
+        .filter(|body| body["Subject"] == "Newsletter title")
+        .map(|body| body["To"].as_str().unwrap().to_string())
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_segments() {
+    let app = spawn_app().await;
+
+    let list = app
+        .api_client
+        .get(format!("{}/admin/segments", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let create = app
+        .post_segment(&json!({"name": "Rustaceans", "definition": r#"{"tag": "rust"}"#}))
+        .await;
+    let tag = app
+        .post_subscriber_tag(&json!({"email": "ursula@example.com", "tag": "rust", "action": "add"}))
+        .await;
+
+    for response in [list, create, tag] {
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn saved_segments_are_listed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let segment_id = create_segment(&app, "Rustaceans", json!({"tag": " Rust "})).await;
+
+    let html_page = app.get_admin_segments_html().await;
+    assert!(html_page.contains("The segment has been saved."));
+    assert!(html_page.contains("Rustaceans"));
+    assert!(html_page.contains(&segment_id));
+    assert!(html_page.contains("&quot;rust&quot;"));
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (json!({"name": "", "definition": r#"{"tag": "rust"}"#}), "between 1 and 100"),
+        (json!({"name": "Broken", "definition": "{"}), "not valid JSON"),
+        (
+            json!({"name": "Unknown", "definition": r#"{"country": "fr"}"#}),
+            "definition is invalid",
+        ),
+        (
+            json!({"name": "Domain", "definition": r#"{"email_domain": "a@b.com"}"#}),
+            "not a valid email domain",
+        ),
+    ];
+
+    for (body, message) in test_cases {
+        let response = app.post_segment(&body).await;
+
+        assert_is_redirect_to(&response, "/admin/segments");
+        let html_page = app.get_admin_segments_html().await;
+        assert!(html_page.contains(message), "Missing `{}`", message);
+    }
+    let saved = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM segments"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn segment_names_are_unique() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_segment(&app, "Rustaceans", json!({"tag": "rust"})).await;
+
+    let response = app
+        .post_segment(&json!({"name": "Rustaceans", "definition": r#"{"tag": "go"}"#}))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/segments");
+    let html_page = app.get_admin_segments_html().await;
+    assert!(html_page.contains("A segment with this name already exists."));
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    tag(&app, "nobody@example.com", "rust").await;
+
+    let html_page = app.get_admin_segments_html().await;
+    assert!(html_page.contains("Unknown subscriber."));
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_matching_subscribers() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber_with_email(&app, "ferris@rust.dev").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "graydon@rust.dev").await;
+    tag(&app, "ferris@rust.dev", "rust").await;
+    tag(&app, "ursula@example.com", "RUST").await;
+    let segment_id = create_segment(
+        &app,
+        "Rustaceans at rust.dev",
+        json!({"all": [{"tag": "rust"}, {"email_domain": "RUST.dev"}]}),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_body(&segment_id)).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(recipients(&app).await, vec!["ferris@rust.dev"]);
+}
+
+#[tokio::test]
+async fn removed_tags_no_longer_match() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber_with_email(&app, "ferris@rust.dev").await;
+    tag(&app, "ferris@rust.dev", "rust").await;
+    let response = app
+        .post_subscriber_tag(&json!({"email": "ferris@rust.dev", "tag": "rust", "action": "remove"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+    let segment_id = create_segment(&app, "Rustaceans", json!({"tag": "rust"})).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_body(&segment_id)).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_segment_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(newsletter_body(&uuid::Uuid::new_v4().to_string()))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_keeps_its_segment_after_the_segment_is_deleted() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber_with_email(&app, "ferris@rust.dev").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    tag(&app, "ferris@rust.dev", "rust").await;
+    let segment_id = create_segment(&app, "Rustaceans", json!({"tag": "rust"})).await;
+    let mut body = newsletter_body(&segment_id);
+    body["send_at"] = json!(chrono::Utc::now() + chrono::Duration::hours(1));
+    let response = app.post_newsletters(body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delete_segment(&segment_id).await;
+    assert_is_redirect_to(&response, "/admin/segments");
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(recipients(&app).await, vec!["ferris@rust.dev"]);
+    let html_page = app.get_admin_segments_html().await;
+    assert!(html_page.contains("The segment has been deleted."));
+    assert!(!html_page.contains("Rustaceans"));
+}
@@ -0,0 +1,174 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use serde_json::{json, Value};
+
+/// Imports pending subscribers, then confirms `confirmed` of them through
+/// the admin API.
+async fn create_subscribers(app: &TestApp, emails: &[&str], confirmed: &[&str]) {
+    let mut body = "email,name\n".to_string();
+    for email in emails {
+        body.push_str(&format!("{},Subscriber\n", email));
+    }
+    let response = app.post_subscriber_import("text/csv", &[], body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    for email in confirmed {
+        let subscriber_id = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .id;
+        let response = app
+            .patch_admin_subscriber(&subscriber_id.to_string(), &json!({"status": "confirmed"}))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+async fn export_csv(app: &TestApp, query: &[(&str, &str)]) -> Vec<Vec<String>> {
+    let response = app.get_subscriber_export(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .map(|line| line.split(',').map(str::to_string).collect())
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_subscriber_export(&[]).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn every_member_of_the_list_is_exported_as_csv() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_subscribers(
+        &app,
+        &["ursula@example.com", "ferris@rust.dev"],
+        &["ferris@rust.dev"],
+    )
+    .await;
+
+    let response = app.get_subscriber_export(&[]).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let body = response.text().await.unwrap();
+    let rows: Vec<Vec<&str>> = body.lines().map(|line| line.split(',').collect()).collect();
+    assert_eq!(
+        rows[0],
+        vec![
+            "id",
+            "email",
+            "name",
+            "status",
+            "subscribed_at",
+            "confirmed_at"
+        ]
+    );
+    assert_eq!(rows.len(), 3);
+    let ursula = rows.iter().find(|r| r[1] == "ursula@example.com").unwrap();
+    let ferris = rows.iter().find(|r| r[1] == "ferris@rust.dev").unwrap();
+    assert_eq!(ursula[3], "pending_confirmation");
+    assert_eq!(ursula[5], "");
+    assert_eq!(ferris[3], "confirmed");
+    assert!(chrono::DateTime::parse_from_rfc3339(ferris[5]).is_ok());
+}
+
+#[tokio::test]
+async fn exports_can_be_filtered_by_status() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_subscribers(
+        &app,
+        &["ursula@example.com", "ferris@rust.dev", "graydon@rust.dev"],
+        &["ferris@rust.dev", "graydon@rust.dev"],
+    )
+    .await;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_subscriptions SET status = 'unsubscribed'
+        WHERE subscriber_id = (SELECT id FROM subscriptions WHERE email = 'graydon@rust.dev')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let confirmed = export_csv(&app, &[("status", "confirmed")]).await;
+    let gone = export_csv(&app, &[("status", "unsubscribed, bounced")]).await;
+
+    assert_eq!(confirmed.len(), 2);
+    assert_eq!(confirmed[1][1], "ferris@rust.dev");
+    assert_eq!(gone.len(), 2);
+    assert_eq!(gone[1][1], "graydon@rust.dev");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_ndjson() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_subscribers(&app, &["ursula@example.com"], &[]).await;
+
+    let response = app.get_subscriber_export(&[("format", "ndjson")]).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+    let body = response.text().await.unwrap();
+    let rows: Vec<Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["email"], "ursula@example.com");
+    assert_eq!(rows[0]["name"], "Subscriber");
+    assert_eq!(rows[0]["status"], "pending_confirmation");
+    assert_eq!(rows[0]["confirmed_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn large_exports_are_streamed_in_full() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let emails: Vec<String> = (0..1000)
+        .map(|i| format!("subscriber-{}@example.com", i))
+        .collect();
+    let emails: Vec<&str> = emails.iter().map(String::as_str).collect();
+    create_subscribers(&app, &emails, &[]).await;
+
+    let rows = export_csv(&app, &[]).await;
+
+    assert_eq!(rows.len(), 1001);
+}
+
+#[tokio::test]
+async fn invalid_export_queries_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let list_id = uuid::Uuid::new_v4().to_string();
+    let test_cases = [
+        vec![("status", "gone")],
+        vec![("status", "confirmed,")],
+        vec![("format", "xml")],
+        vec![("list_id", list_id.as_str())],
+    ];
+
+    for query in test_cases {
+        let response = app.get_subscriber_export(&query).await;
+
+        assert_eq!(response.status().as_u16(), 400, "{:?}", query);
+    }
+}
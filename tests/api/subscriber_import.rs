@@ -0,0 +1,285 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn import_csv(app: &TestApp, query: &[(&str, &str)], body: &str) -> Value {
+    let response = app
+        .post_subscriber_import("text/csv", query, body.to_string())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+async fn membership_statuses(app: &TestApp) -> Vec<(String, bool, String)> {
+    sqlx::query!(
+        r#"
+        SELECT s.email, s.address_confirmed, m.status
+        FROM newsletter_subscriptions m
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        ORDER BY s.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, r.address_confirmed, r.status))
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriber_import("text/csv", &[], "email,name\n".into())
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn csv_rows_are_imported_as_pending_without_sending_emails() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let report = import_csv(
+        &app,
+        &[],
+        "email,name,locale\n\
+        ursula@example.com,Ursula,fr\n\
+        not-an-email,Ferris,\n\
+        graydon@example.com,Graydon,\n",
+    )
+    .await;
+
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["duplicates"], 0);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["errors"][0]["line"], 3);
+    assert!(report["errors"][0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("not-an-email"));
+    let pending = "pending_confirmation".to_string();
+    assert_eq!(
+        membership_statuses(&app).await,
+        vec![
+            ("graydon@example.com".into(), false, pending.clone()),
+            ("ursula@example.com".into(), false, pending),
+        ]
+    );
+    let locale =
+        sqlx::query!("SELECT locale FROM subscriptions WHERE email = 'ursula@example.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .locale;
+    assert_eq!(locale.as_deref(), Some("fr"));
+}
+
+#[tokio::test]
+async fn json_lines_can_be_imported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_import(
+            "application/x-ndjson",
+            &[],
+            [
+                json!({"email": "ursula@example.com", "name": "Ursula"}),
+                json!({"email": "ferris@rust.dev", "name": ""}),
+            ]
+            .iter()
+            .map(|row| row.to_string() + "\n")
+            .collect(),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["errors"][0]["line"], 2);
+}
+
+#[tokio::test]
+async fn pre_confirmed_imports_need_a_consent_source() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_import(
+            "text/csv",
+            &[("pre_confirmed", "true")],
+            "email,name\nursula@example.com,Ursula\n".into(),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(membership_statuses(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn pre_confirmed_subscribers_receive_the_next_issue() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let report = import_csv(
+        &app,
+        &[
+            ("pre_confirmed", "true"),
+            ("consent_source", "Signup form on the old website"),
+        ],
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let confirmed = "confirmed".to_string();
+    assert_eq!(
+        membership_statuses(&app).await,
+        vec![("ursula@example.com".into(), true, confirmed)]
+    );
+    let import = sqlx::query!(
+        r#"
+        SELECT i.import_id, i.consent_source, i.imported, i.imported_by, u.username
+        FROM subscriber_imports i
+        JOIN newsletter_subscriptions m ON m.import_id = i.import_id
+        JOIN users u ON u.user_id = i.imported_by
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(report["import_id"], import.import_id.to_string());
+    assert_eq!(
+        import.consent_source.as_deref(),
+        Some("Signup form on the old website")
+    );
+    assert_eq!(import.imported, 1);
+    assert_eq!(import.username, app.test_user.username);
+}
+
+#[tokio::test]
+async fn addresses_already_on_the_list_are_left_untouched() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_csv(&app, &[], "email,name\nursula@example.com,Ursula\n").await;
+    sqlx::query!("UPDATE newsletter_subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let report = import_csv(
+        &app,
+        &[("pre_confirmed", "true"), ("consent_source", "Conference")],
+        "email,name\n\
+        ursula@example.com,Someone else\n\
+        ferris@rust.dev,Ferris\n\
+        ferris@rust.dev,Ferris again\n",
+    )
+    .await;
+
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["duplicates"], 2);
+    assert_eq!(
+        membership_statuses(&app).await,
+        vec![
+            ("ferris@rust.dev".into(), true, "confirmed".into()),
+            ("ursula@example.com".into(), false, "unsubscribed".into()),
+        ]
+    );
+    let names = sqlx::query!("SELECT name FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(names[0].name, "Ferris");
+    assert_eq!(names[1].name, "Ursula");
+}
+
+#[tokio::test]
+async fn existing_subscribers_can_be_imported_into_another_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_csv(&app, &[], "email,name\nursula@example.com,Ursula\n").await;
+    let list_id = app
+        .create_newsletter_list("Weekly digest")
+        .await
+        .to_string();
+
+    let report = import_csv(
+        &app,
+        &[("list_id", &list_id)],
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await;
+
+    assert_eq!(report["imported"], 1);
+    assert_eq!(membership_statuses(&app).await.len(), 2);
+}
+
+#[tokio::test]
+async fn large_uploads_are_imported_in_batches() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut body = "email,name\n".to_string();
+    for i in 0..1200 {
+        body.push_str(&format!("subscriber-{}@example.com,Subscriber {}\n", i, i));
+    }
+
+    let report = import_csv(&app, &[], &body).await;
+
+    assert_eq!(report["imported"], 1200);
+    assert_eq!(membership_statuses(&app).await.len(), 1200);
+}
+
+#[tokio::test]
+async fn malformed_uploads_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = [
+        ("application/json", "[]", "an unsupported content type"),
+        (
+            "text/csv",
+            "address,full_name\n",
+            "no email and name columns",
+        ),
+    ];
+
+    for (content_type, body, description) in test_cases {
+        let response = app
+            .post_subscriber_import(content_type, &[], body.into())
+            .await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with a 400 for {}.",
+            description
+        );
+    }
+}
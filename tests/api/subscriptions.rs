@@ -1,4 +1,4 @@
-use reqwest::{Client, Response};
+use reqwest::Response;
 use crate::helpers::{spawn_app, TestApp};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
@@ -27,14 +27,14 @@ async fn subscribe_persists_the_new_subscriber(){
 
     app.post_subscriptions(body.into()).await;
     
-    let saved =  sqlx::query!(r#"SELECT email, name, status FROM subscriptions"#,)
+    let saved =  sqlx::query!(r#"SELECT email, name, address_confirmed FROM subscriptions"#,)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
     
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "pending_confirmation");
+    assert!(!saved.address_confirmed);
 }
 
 #[tokio::test]
@@ -95,13 +95,45 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data(){
         .await;
     
     app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
+#[tokio::test]
+async fn subscribe_retries_the_confirmation_email_on_a_transient_failure(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+    // The queue tries again later, rather than the email client straight away.
+    sqlx::query!("UPDATE confirmation_email_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error(){
     let app = spawn_app().await;
@@ -117,4 +149,340 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error(){
         app.post_subscriptions(body.into()).await;
 
     assert_eq!(response.status().as_u16(), 500);
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_new_confirmation_email(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+    let second = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&requests[0]);
+    let second_links = app.get_confirmation_links(&requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_changes_nothing(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let confirmation_links = {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_subscriptions(body.into()).await;
+        app.dispatch_confirmation_emails().await;
+        let email_request = &app.email_server.received_requests().await.unwrap()[0];
+        app.get_confirmation_links(email_request)
+    };
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT address_confirmed FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert!(saved[0].address_confirmed);
+}
+
+#[tokio::test]
+async fn an_unsubscribed_subscriber_can_opt_in_again(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+    sqlx::query!("UPDATE newsletter_subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM newsletter_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM newsletter_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_subscriber_who_complained_is_not_sent_another_confirmation_link(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+    sqlx::query!("UPDATE newsletter_subscriptions SET status = 'complained'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM newsletter_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "complained");
+}
+
+#[tokio::test]
+async fn unknown_statuses_are_rejected_by_the_database(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    let membership = sqlx::query!("UPDATE newsletter_subscriptions SET status = 'deleted'")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(membership.is_err());
+}
+
+#[tokio::test]
+async fn subscribe_to_a_specific_list_only_joins_that_list(){
+    let app = spawn_app().await;
+    let list_id = app.create_newsletter_list("Rust weekly").await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={}",
+        list_id
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_subscriptions(body).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_confirmation_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let memberships = sqlx::query!("SELECT newsletter_id, status FROM newsletter_subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(memberships.len(), 1);
+    assert_eq!(memberships[0].newsletter_id, list_id);
+    assert_eq!(memberships[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_an_unknown_list(){
+    let app = spawn_app().await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={}",
+        uuid::Uuid::new_v4()
+    );
+
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_sent_in_the_subscriber_locale(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr_FR";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Confirmez votre inscription à Newsletter");
+    assert!(email["TextBody"].as_str().unwrap().starts_with("Bonjour le guin,"));
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale.as_deref(), Some("fr-fr"));
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_an_invalid_locale(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=..%2Fen";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn concurrent_signups_with_the_same_address_both_succeed(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let (first, second) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn a_repeat_signup_only_changes_the_locale_once_confirmed(){
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_confirmation_emails().await;
+    let saved_locale = || async {
+        sqlx::query!("SELECT locale FROM subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .locale
+    };
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr".into())
+        .await;
+    app.dispatch_confirmation_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(saved_locale().await, None);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Confirmez votre inscription à Newsletter");
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(saved_locale().await.as_deref(), Some("fr"));
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_even_if_the_email_api_fails(){
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_confirmation_emails().await;
+    let task = sqlx::query!("SELECT n_retries FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+    // No link was sent, so none can be used.
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
@@ -1,6 +1,6 @@
-use linkify::LinkKind;
-use crate::helpers::{spawn_app, ConfirmationLinks};
-use reqwest::Url;
+use crate::helpers::{
+    create_unconfirmed_subscriber, spawn_app, spawn_app_with, ConfirmationLinks,
+};
 use wiremock::{ResponseTemplate, Mock, Request};
 use wiremock::matchers::{path, method};
 
@@ -27,9 +27,10 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called(){
         .await;
     
     app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
 
     let email_request: &Request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links: ConfirmationLinks = app.get_confirmation_links(&email_request);
+    let confirmation_links: ConfirmationLinks = app.get_confirmation_links(email_request);
     
     let response = reqwest::get(confirmation_links.html)
         .await
@@ -37,4 +38,213 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called(){
     
     assert_eq!(response.status().as_u16(), 200);
     
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Your subscription is confirmed"));
+    let saved = sqlx::query!(
+        "SELECT s.address_confirmed, t.consumed_at \
+        FROM subscriptions s JOIN subscription_tokens t ON t.subscriber_id = s.id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert!(saved.address_confirmed);
+    assert!(saved.consumed_at.is_some());
+}
+
+#[tokio::test]
+async fn a_confirmation_link_cannot_be_used_twice() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.text().await.unwrap().contains("already been used"));
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_a_410() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response.text().await.unwrap().contains("has expired"));
+    let saved = sqlx::query!("SELECT address_confirmed FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert!(!saved.address_confirmed);
+}
+
+#[tokio::test]
+async fn a_link_for_a_subscriber_who_unsubscribed_since_is_rejected_with_a_409() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE newsletter_subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.text().await.unwrap().contains("no longer valid"));
+    let saved = sqlx::query!("SELECT status FROM newsletter_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_unknown_confirmation_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["Content-Type"], "text/html; charset=utf-8");
+    assert!(response.text().await.unwrap().contains("not valid"));
+}
+
+#[tokio::test]
+async fn confirm_returns_a_500_page_if_there_is_a_fatal_database_error() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN consumed_at;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 500);
+    assert!(response.text().await.unwrap().contains("Something went wrong"));
+}
+
+#[tokio::test]
+async fn a_welcome_email_is_sent_once_the_subscription_is_confirmed() {
+    let app = spawn_app_with(|c| c.welcome_email.enabled = true).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_welcome_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Welcome to Newsletter");
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("Hi le guin,"));
+    assert!(text.contains("/subscriptions/unsubscribe?token="));
+}
+
+#[tokio::test]
+async fn the_welcome_email_subject_can_be_configured() {
+    let app = spawn_app_with(|c| {
+        c.welcome_email.enabled = true;
+        c.welcome_email.subject = Some("Glad to have you, {{name}}".into());
+    })
+    .await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(confirmation_links.html).await.unwrap();
+    app.dispatch_welcome_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Glad to have you, le guin");
+}
+
+#[tokio::test]
+async fn no_welcome_email_is_sent_when_disabled() {
+    let app = spawn_app_with(|c| c.welcome_email.enabled = false).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_welcome_emails().await;
+
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM welcome_email_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[tokio::test]
+async fn a_failed_welcome_email_does_not_undo_the_confirmation_and_is_retried_later() {
+    let app = spawn_app_with(|c| c.welcome_email.enabled = true).await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    app.dispatch_welcome_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT address_confirmed FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.address_confirmed);
+    let queued = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"postponed!\" FROM welcome_email_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.postponed);
+}
@@ -0,0 +1,255 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn membership_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM newsletter_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+fn bounce(email: &str, bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "MessageStream": "outbound",
+        "ID": 4323372036854775807u64,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Description": "The server was unable to deliver your message.",
+        "Email": email,
+        "From": "sender@example.com",
+        "BouncedAt": "2025-09-01T16:33:54.9070259Z",
+        "Inactive": true,
+        "CanActivate": true
+    })
+}
+
+#[tokio::test]
+async fn requests_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    let body = bounce("ursula@example.com", "HardBounce");
+
+    let missing = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let wrong = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .basic_auth(&app.postmark_webhook.username, Some(Uuid::new_v4().to_string()))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+
+    for response in [missing, wrong] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="webhooks""#
+        );
+    }
+    let events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, 0);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_is_recorded_and_stops_further_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let response = app.post_postmark_webhook(&bounce(&email, "HardBounce")).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let event = sqlx::query!("SELECT event_type, bounce_type, email, message_id FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.event_type, "bounce");
+    assert_eq!(event.bounce_type.as_deref(), Some("HardBounce"));
+    assert_eq!(event.email, email);
+    assert_eq!(
+        event.message_id.as_deref(),
+        Some("883953f4-6105-42a2-a16a-77a8eac79483")
+    );
+    assert_eq!(membership_status(&app).await, "bounced");
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_soft_bounce_is_recorded_without_unsubscribing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let response = app.post_postmark_webhook(&bounce(&email, "SoftBounce")).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let event = sqlx::query!("SELECT bounce_type FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.bounce_type.as_deref(), Some("SoftBounce"));
+    assert_eq!(membership_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_spam_complaint_marks_the_subscriber_as_complained() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let mut body = bounce(&email, "SpamComplaint");
+    body["RecordType"] = "SpamComplaint".into();
+
+    let response = app.post_postmark_webhook(&body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let event = sqlx::query!("SELECT event_type FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.event_type, "spam_complaint");
+    assert_eq!(membership_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn a_delivery_is_recorded() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Delivery",
+            "MessageStream": "outbound",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": "ursula@example.com",
+            "DeliveredAt": "2025-09-01T16:33:54Z",
+            "Details": "Test delivery webhook details"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let event = sqlx::query!("SELECT event_type, email, payload FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.event_type, "delivery");
+    assert_eq!(event.email, "ursula@example.com");
+    assert_eq!(event.payload["Details"], "Test delivery webhook details");
+}
+
+#[tokio::test]
+async fn redelivered_events_are_recorded_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let body = bounce(&email, "SoftBounce");
+
+    for _ in 0..2 {
+        let response = app.post_postmark_webhook(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, 1);
+}
+
+#[tokio::test]
+async fn bounces_match_the_address_whatever_its_case() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let response = app
+        .post_postmark_webhook(&bounce(&email.to_uppercase(), "HardBounce"))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(membership_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn unsupported_events_are_acknowledged_and_dropped() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Open",
+            "Recipient": "ursula@example.com"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, 0);
+}
+
+#[tokio::test]
+async fn malformed_events_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"Email": "ursula@example.com"}), "missing record type"),
+        (
+            serde_json::json!({"RecordType": "Bounce", "Type": "HardBounce"}),
+            "missing email",
+        ),
+        (
+            serde_json::json!({
+                "RecordType": "Bounce",
+                "Type": "HardBounce",
+                "Email": "ursula@example.com",
+                "BouncedAt": "yesterday"
+            }),
+            "invalid date",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_postmark_webhook(&body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The webhook did not fail with a 400 when the payload was {}.",
+            description
+        );
+    }
+}